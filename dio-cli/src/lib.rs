@@ -1,17 +1,56 @@
-use serde::Deserialize;
+pub use dio_core::{DataFile, Entry, Kind, TagFilter};
 
-#[derive(Debug, Clone, Copy)]
-pub enum StoreCount {
-    Facts = 12isize,
-    Principles = 14isize,
+/// Lowercase alphanumeric words of `text`.
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
 }
 
-#[derive(Deserialize, Debug, Clone)]
-pub struct DioFacts {
-    pub facts: Vec<String>,
+/// Ranks `entries` by how often their text contains the words of `query`,
+/// best first, ties in the order of `entries`.
+pub fn search<'a>(entries: &'a [Entry], query: &str) -> Vec<&'a Entry> {
+    let terms = words(query);
+    let mut ranked: Vec<(usize, &Entry)> = entries
+        .iter()
+        .map(|entry| {
+            let hits = words(&entry.text)
+                .iter()
+                .filter(|word| terms.contains(word))
+                .count();
+            (hits, entry)
+        })
+        .filter(|(hits, _)| *hits > 0)
+        .collect();
+    ranked.sort_by_key(|(hits, _)| std::cmp::Reverse(*hits));
+    ranked.into_iter().map(|(_, entry)| entry).collect()
 }
 
-#[derive(Deserialize, Debug, Clone)]
-pub struct DioPrinciples {
-    pub principles: Vec<String>,
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranks_entries_by_matching_words() {
+        let kind = Kind::new("facts").unwrap();
+        let entries: Vec<Entry> = (1..)
+            .zip([
+                "Honey never spoils",
+                "Octopuses have three hearts",
+                "Bees make honey, honey!",
+            ])
+            .map(|(id, text)| Entry::new(kind.clone(), id, text).unwrap())
+            .collect();
+        let keys = |query| {
+            (search(&entries, query).into_iter())
+                .map(|entry| entry.id)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(keys("HONEY"), [3, 1]);
+        assert_eq!(keys("hearts of honey"), [3, 1, 2]);
+        assert_eq!(search(&entries, "spoils"), [&entries[0]]);
+        assert!(keys("wasps").is_empty());
+    }
 }
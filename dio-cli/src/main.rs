@@ -2,12 +2,24 @@
 //!
 //! ```bash
 //! $ cargo install --path .
+//! $ dio kinds
+//! facts: 12
+//! principles: 14
 //! $ dio --option facts --key 12
-//! fact 12: Lorem ipsum dolor sit amet, consectetur
+//! Lorem ipsum dolor sit amet, consectetur
+//! $ dio search lorem --option facts
+//! facts 12: Lorem ipsum dolor sit amet, consectetur
+//! $ dio list --option principles --tag health
+//! principles 3: Sleep eight hours
 //! ```
+//!
+//! Kinds are the keys of `data.json`, so adding one only takes a new list.
+//! Entries can be given as `{"title": "...", "tags": ["health"]}` to be
+//! filtered with `--tag health,sleep` (both) or `--tag 'health|sleep'`
+//! (either).
 
-use clap::Parser;
-use dio_cli::{DioFacts, DioPrinciples, StoreCount};
+use clap::{Parser, Subcommand};
+use dio_cli::{DataFile, Entry, Kind, TagFilter};
 use dotenv::dotenv;
 use std::fs::File;
 
@@ -21,54 +33,147 @@ fn main() {
 #[derive(Parser, Debug)]
 #[command(author,version,about,long_about = None)]
 pub(crate) struct Args {
-    /// Request option for either principles or facts.
+    #[command(subcommand)]
+    pub(crate) command: Option<Command>,
+
+    /// Kind of entry to display, such as principles or facts.
     #[arg(short, long)]
-    pub(crate) option: String,
+    pub(crate) option: Option<String>,
 
-    /// Key number of Principles or Facts to display.
+    /// Key number of the entry to display, counting only entries with `--tag`
+    /// if given.
     #[arg(short, long, default_value_t = 1)]
-    pub(crate) key: u8,
+    pub(crate) key: usize,
+
+    /// Only pick among entries with these tags.
+    #[arg(short, long)]
+    pub(crate) tag: Option<TagFilter>,
 }
+#[derive(Subcommand, Debug)]
+pub(crate) enum Command {
+    /// Search the entries in data.json.
+    Search {
+        /// Words to look for.
+        query: String,
+
+        /// Only search entries of this kind.
+        #[arg(short, long)]
+        option: Option<String>,
+
+        /// Only search entries with these tags.
+        #[arg(short, long)]
+        tag: Option<TagFilter>,
+    },
+
+    /// List the entries in data.json.
+    List {
+        /// Only list entries of this kind.
+        #[arg(short, long)]
+        option: Option<String>,
+
+        /// Only list entries with these tags.
+        #[arg(short, long)]
+        tag: Option<TagFilter>,
+    },
+
+    /// List the kinds in data.json, with their number of entries.
+    Kinds,
+}
+
 fn main_cli() {
     let args = Args::parse();
-    match args.option.as_str() {
-        "principles" => Dio::handle_principles(args),
-        "facts" => Dio::handle_facts(args),
-        _ => println!("Invalid option. Please use either 'principles' or 'facts'"),
+    match &args.command {
+        Some(Command::Search { query, option, tag }) => {
+            Dio::handle_search(query, option.as_deref(), tag.as_ref())
+        }
+        Some(Command::List { option, tag }) => Dio::handle_list(option.as_deref(), tag.as_ref()),
+        Some(Command::Kinds) => Dio::handle_kinds(),
+        None => Dio::handle_entry(args),
     }
 }
 
 #[derive(Default, Debug)]
 pub struct Dio;
 impl Dio {
-    /// .
-    fn handle_facts(args: Args) {
-        if !(0u8 < args.key && args.key <= (StoreCount::Facts as u8)) {
+    /// Prints the entry of kind `--option` with key `--key`.
+    fn handle_entry(args: Args) {
+        let data = Self::read_data();
+        let Some(kind) = Self::kind_of(&data, args.option.as_deref()) else {
+            return Self::invalid_option(&data);
+        };
+        let entries = Self::read_entries(&data, &kind, args.tag.as_ref());
+        if !(0 < args.key && args.key <= entries.len()) {
             eprintln!("Index out of bounds");
             std::process::exit(1);
         }
-        let facts = Self::read_file_facts();
-        let fact: &String = &facts[args.key as usize - 1];
-        println!("{}", fact);
+        println!("{}", entries[args.key - 1].text);
     }
 
-    /// .
-    fn handle_principles(args: Args) {
-        if !(0u8 < args.key && args.key <= (StoreCount::Principles as u8)) {
-            eprintln!("Index out of bounds");
-            std::process::exit(1);
+    /// Prints every kind with its number of entries.
+    fn handle_kinds() {
+        let data = Self::read_data();
+        for (kind, texts) in &data.0 {
+            println!("{kind}: {}", texts.len());
         }
-        let principles = Self::read_file_principles();
-        let principle: &String = &principles[args.key as usize - 1];
-        println!("{}", principle);
     }
 
-    /// .
-    ///
-    /// # Panics
+    /// Prints the entries of every kind, or of `option`, as `<kind> <key>: <text>`.
+    fn handle_list(option: Option<&str>, tag: Option<&TagFilter>) {
+        let data = Self::read_data();
+        let Some(kinds) = Self::kinds_of(&data, option) else {
+            return Self::invalid_option(&data);
+        };
+        for kind in kinds {
+            for entry in Self::read_entries(&data, &kind, tag) {
+                println!("{kind} {}: {}", entry.id, entry.text);
+            }
+        }
+    }
+
+    /// Prints matching entries as `<kind> <key>: <text>`, best match first
+    /// within each kind.
+    fn handle_search(query: &str, option: Option<&str>, tag: Option<&TagFilter>) {
+        let data = Self::read_data();
+        let Some(kinds) = Self::kinds_of(&data, option) else {
+            return Self::invalid_option(&data);
+        };
+        let mut found = Vec::new();
+        for kind in kinds {
+            let entries = Self::read_entries(&data, &kind, tag);
+            for entry in dio_cli::search(&entries, query) {
+                found.push(format!("{kind} {}: {}", entry.id, entry.text));
+            }
+        }
+        if found.is_empty() {
+            println!("No matches for '{query}'");
+        }
+        found.iter().for_each(|line| println!("{line}"));
+    }
+
+    /// The kind named `option`, if data.json has it.
+    fn kind_of(data: &DataFile, option: Option<&str>) -> Option<Kind> {
+        let kind: Kind = option?.parse().ok()?;
+        data.0.contains_key(&kind).then_some(kind)
+    }
+
+    /// Every kind of `data`, or only `option` if given. `None` if `data` has
+    /// no such kind.
+    fn kinds_of(data: &DataFile, option: Option<&str>) -> Option<Vec<Kind>> {
+        match option {
+            None => Some(data.kinds().cloned().collect()),
+            Some(_) => Self::kind_of(data, option).map(|kind| vec![kind]),
+        }
+    }
+
+    fn invalid_option(data: &DataFile) {
+        let kinds: Vec<_> = data.kinds().map(Kind::as_str).collect();
+        println!("Invalid option. Please use one of: {}", kinds.join(", "));
+    }
+
+    /// The contents of data.json.
     ///
-    /// Panics if .
-    fn read_file_facts() -> Vec<String> {
+    /// Exits if the file cannot be opened or read.
+    fn read_data() -> DataFile {
         let rdr: File = match File::open::<&str>("data.json") {
             Ok(t) => t,
             Err(_) => {
@@ -76,30 +181,60 @@ impl Dio {
                 std::process::exit(1);
             }
         };
-        match serde_json::from_reader::<_, DioFacts>(&rdr) {
+        match serde_json::from_reader::<_, DataFile>(&rdr) {
             Ok(contents) => contents,
-            _ => panic!("Could not read file"),
+            Err(err) => {
+                eprintln!("Could not read file: {err}");
+                std::process::exit(1);
+            }
         }
-        .facts
     }
 
-    /// .
-    ///
-    /// # Panics
+    /// The entries of `kind` in `data` that pass `tag`, with whitespace
+    /// normalized.
     ///
-    /// Panics if .
-    fn read_file_principles() -> Vec<String> {
-        let rdr: File = match File::open::<&str>("data.json") {
-            Ok(t) => t,
-            Err(_) => {
-                println!("Failed to read file");
+    /// Exits if one of them is invalid.
+    fn read_entries(data: &DataFile, kind: &Kind, tag: Option<&TagFilter>) -> Vec<Entry> {
+        match data.entries(kind) {
+            Ok(mut entries) => {
+                entries.retain(|entry| tag.is_none_or(|tag| tag.matches(entry)));
+                entries
+            }
+            Err(err) => {
+                eprintln!("Invalid entry in data.json: {err}");
                 std::process::exit(1);
             }
-        };
-        match serde_json::from_reader::<_, DioPrinciples>(&rdr) {
-            Ok(contents) => contents,
-            _ => panic!("Could not read file"),
         }
-        .principles
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters_entries_by_tag() {
+        let data: DataFile = serde_json::from_str(
+            r#"{"facts": [
+                {"title": "Sleep well", "tags": ["health", "sleep"]},
+                {"title": "Eat greens", "tags": ["Health"]},
+                "Honey never spoils"
+            ]}"#,
+        )
+        .unwrap();
+        let kind = Kind::new("facts").unwrap();
+        let keys = |tag: &str| {
+            let args = Args::try_parse_from(["dio", "--tag", tag]).unwrap();
+            (Dio::read_entries(&data, &kind, args.tag.as_ref()).into_iter())
+                .map(|entry| entry.id)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(keys("health,sleep"), [1]);
+        assert_eq!(keys("sleep|health"), [1, 2]);
+        assert!(keys("food").is_empty());
+        assert_eq!(Dio::read_entries(&data, &kind, None).len(), 3);
+        assert!(Args::try_parse_from(["dio", "--tag", "a,b|c"]).is_err());
+        assert!(Args::try_parse_from(["dio", "search", "x", "--tag", "no spaces"]).is_err());
     }
 }
@@ -0,0 +1,467 @@
+//! `dio-core` holds what dio-server and dio-cli agree on: the [`Entry`] type,
+//! the rules an entry follows, and the [`DataFile`] entries are read from.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{borrow::Borrow, collections::BTreeMap, fmt, str::FromStr};
+
+/// Longest text of an entry, in characters.
+pub const MAX_TEXT_CHARS: usize = 1000;
+/// Longest name of a kind.
+pub const MAX_KIND_LEN: usize = 32;
+pub const MAX_METADATA_ENTRIES: usize = 32;
+pub const MAX_METADATA_KEY_LEN: usize = 64;
+/// Longest metadata value, in characters.
+pub const MAX_METADATA_VALUE_CHARS: usize = 1000;
+pub const MAX_TAGS: usize = 16;
+pub const MAX_TAG_LEN: usize = 32;
+
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+pub enum ValidationError {
+    #[error("Text is empty")]
+    EmptyText,
+
+    #[error("Text is {chars} characters long, over the limit of {max}", max = MAX_TEXT_CHARS)]
+    TextTooLong { chars: usize },
+
+    #[error("Text has leading, trailing or repeated whitespace")]
+    Whitespace,
+
+    #[error(
+        "Invalid kind `{0}`: expected 1 to {max} lowercase letters, digits or `_`, \
+         starting with a letter",
+        max = MAX_KIND_LEN
+    )]
+    Kind(String),
+
+    #[error("More than {max} metadata entries", max = MAX_METADATA_ENTRIES)]
+    TooMuchMetadata,
+
+    #[error(
+        "Invalid metadata key `{0}`: expected 1 to {max} letters, digits, `_` or `-`",
+        max = MAX_METADATA_KEY_LEN
+    )]
+    MetadataKey(String),
+
+    #[error("Metadata `{0}` is over the limit of {max} characters", max = MAX_METADATA_VALUE_CHARS)]
+    MetadataValueTooLong(String),
+
+    #[error("More than {max} tags", max = MAX_TAGS)]
+    TooManyTags,
+
+    #[error("Tags are not sorted, or repeat")]
+    UnsortedTags,
+
+    #[error("Tag filters separate tags with either `,` or `|`, not both")]
+    TagFilter,
+
+    #[error(
+        "Invalid tag `{0}`: expected 1 to {max} lowercase letters, digits, `_` or `-`",
+        max = MAX_TAG_LEN
+    )]
+    Tag(String),
+}
+
+/// What an entry is, such as `facts` or `principles`. Ids are only unique
+/// within a kind.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema), schema(value_type = String, example = "facts"))]
+pub struct Kind(String);
+
+impl Kind {
+    pub fn new(name: impl Into<String>) -> Result<Self, ValidationError> {
+        let name = name.into();
+        let mut chars = name.chars();
+        let valid = name.len() <= MAX_KIND_LEN
+            && chars.next().is_some_and(|c| c.is_ascii_lowercase())
+            && chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+        match valid {
+            true => Ok(Kind(name)),
+            false => Err(ValidationError::Kind(name)),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl TryFrom<String> for Kind {
+    type Error = ValidationError;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        Kind::new(name)
+    }
+}
+
+impl FromStr for Kind {
+    type Err = ValidationError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Kind::new(name)
+    }
+}
+
+impl From<Kind> for String {
+    fn from(kind: Kind) -> Self {
+        kind.0
+    }
+}
+
+impl Borrow<str> for Kind {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for Kind {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// A fact, a principle, or an entry of any other kind.
+///
+/// The text is serialized as `title`, the name stored documents and clients
+/// already use.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[cfg_attr(
+    feature = "utoipa",
+    schema(example = json!({
+        "kind": "facts",
+        "id": 1,
+        "title": "Water boils at 100 °C at sea level.",
+        "tags": ["physics"],
+        "created_at": "2024-01-01T00:00:00Z"
+    }))
+)]
+pub struct Entry {
+    pub kind: Kind,
+
+    /// Unique within the kind, from 1.
+    pub id: i32,
+
+    #[serde(rename = "title")]
+    pub text: String,
+
+    /// Free-form attributes, such as a source or an author.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+
+    /// Themes to group entries by, such as `health`. Sorted, without duplicates.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+impl Entry {
+    /// An entry of `text`, normalized, without metadata, tags or timestamps.
+    pub fn new(kind: Kind, id: i32, text: &str) -> Result<Self, ValidationError> {
+        let mut entry = Entry {
+            kind,
+            id,
+            text: text.to_owned(),
+            metadata: BTreeMap::new(),
+            tags: Vec::new(),
+            created_at: None,
+            updated_at: None,
+        };
+        entry.normalize()?;
+        Ok(entry)
+    }
+
+    /// Normalizes the whitespace of the text and metadata values, and the
+    /// tags, then [validates](Entry::validate) the entry.
+    pub fn normalize(&mut self) -> Result<(), ValidationError> {
+        self.text = normalize_whitespace(&self.text);
+        for value in self.metadata.values_mut() {
+            *value = normalize_whitespace(value);
+        }
+        self.tags = normalize_tags(&self.tags);
+        self.validate()
+    }
+
+    /// Checks the entry follows the rules, without changing it.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        if self.text.is_empty() {
+            return Err(ValidationError::EmptyText);
+        }
+        let chars = self.text.chars().count();
+        if chars > MAX_TEXT_CHARS {
+            return Err(ValidationError::TextTooLong { chars });
+        }
+        if self.text != normalize_whitespace(&self.text) {
+            return Err(ValidationError::Whitespace);
+        }
+
+        if self.metadata.len() > MAX_METADATA_ENTRIES {
+            return Err(ValidationError::TooMuchMetadata);
+        }
+        for (key, value) in &self.metadata {
+            let valid_key = !key.is_empty()
+                && key.len() <= MAX_METADATA_KEY_LEN
+                && (key.chars()).all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+            if !valid_key {
+                return Err(ValidationError::MetadataKey(key.clone()));
+            }
+            if value.chars().count() > MAX_METADATA_VALUE_CHARS {
+                return Err(ValidationError::MetadataValueTooLong(key.clone()));
+            }
+        }
+
+        if self.tags.len() > MAX_TAGS {
+            return Err(ValidationError::TooManyTags);
+        }
+        for tag in &self.tags {
+            validate_tag(tag)?;
+        }
+        if self.tags != normalize_tags(&self.tags) {
+            return Err(ValidationError::UnsortedTags);
+        }
+        Ok(())
+    }
+
+    /// Whether the entry carries `tag`.
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags
+            .binary_search_by(|own| own.as_str().cmp(tag))
+            .is_ok()
+    }
+}
+
+/// `tag` trimmed and lowercased, the form entries keep tags in.
+pub fn normalize_tag(tag: &str) -> String {
+    tag.trim().to_lowercase()
+}
+
+/// `tags` [normalized](normalize_tag), sorted and without duplicates.
+pub fn normalize_tags(tags: &[String]) -> Vec<String> {
+    let mut tags: Vec<_> = tags.iter().map(|tag| normalize_tag(tag)).collect();
+    tags.sort_unstable();
+    tags.dedup();
+    tags
+}
+
+/// Checks `tag` is a valid, normalized tag.
+pub fn validate_tag(tag: &str) -> Result<(), ValidationError> {
+    let valid = !tag.is_empty()
+        && tag.len() <= MAX_TAG_LEN
+        && (tag.chars())
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-');
+    match valid {
+        true => Ok(()),
+        false => Err(ValidationError::Tag(tag.to_owned())),
+    }
+}
+
+/// `text` with every run of whitespace turned into one space, and none left
+/// at either end.
+pub fn normalize_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Tags entries are filtered by, written `health,sleep` to require all of
+/// them, or `health|sleep` to require any of them.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TagFilter {
+    /// Normalized.
+    pub tags: Vec<String>,
+    /// Whether one of the tags is enough.
+    pub any: bool,
+}
+
+impl TagFilter {
+    pub fn matches(&self, entry: &Entry) -> bool {
+        match self.any {
+            true => self.tags.is_empty() || self.tags.iter().any(|tag| entry.has_tag(tag)),
+            false => self.tags.iter().all(|tag| entry.has_tag(tag)),
+        }
+    }
+}
+
+impl FromStr for TagFilter {
+    type Err = ValidationError;
+
+    fn from_str(filter: &str) -> Result<Self, Self::Err> {
+        let any = filter.contains('|');
+        if any && filter.contains(',') {
+            return Err(ValidationError::TagFilter);
+        }
+        let tags = (filter.split([',', '|']))
+            .map(|tag| {
+                let tag = normalize_tag(tag);
+                validate_tag(&tag).map(|()| tag)
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(TagFilter { tags, any })
+    }
+}
+
+/// The `data.json` read by dio-cli and `dio-server seed`: the entries of each
+/// kind, such as `{"facts": [...], "principles": [...]}`. The n-th entry of a
+/// kind has id n.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct DataFile(pub BTreeMap<Kind, Vec<DataItem>>);
+
+/// An entry of a [`DataFile`]: its text, or its text and tags, as in
+/// `{"title": "...", "tags": ["health"]}`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum DataItem {
+    Text(String),
+    Tagged {
+        title: String,
+        #[serde(default)]
+        tags: Vec<String>,
+    },
+}
+
+/// An entry of a [`DataFile`] that breaks the rules.
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+#[error("{kind} {id}: {error}")]
+pub struct InvalidEntry {
+    pub kind: Kind,
+    pub id: i32,
+    pub error: ValidationError,
+}
+
+impl DataFile {
+    pub fn kinds(&self) -> impl Iterator<Item = &Kind> {
+        self.0.keys()
+    }
+
+    /// The entries of `kind`, normalized, none if the file has no such kind.
+    pub fn entries(&self, kind: &Kind) -> Result<Vec<Entry>, InvalidEntry> {
+        let items = self.0.get(kind).map(Vec::as_slice).unwrap_or_default();
+        (1..)
+            .zip(items)
+            .map(|(id, item)| {
+                let entry = match item {
+                    DataItem::Text(text) => Entry::new(kind.clone(), id, text),
+                    DataItem::Tagged { title, tags } => Entry::new(kind.clone(), id, title)
+                        .and_then(|mut entry| {
+                            entry.tags.clone_from(tags);
+                            entry.normalize().map(|()| entry)
+                        }),
+                };
+                entry.map_err(|error| InvalidEntry {
+                    kind: kind.clone(),
+                    id,
+                    error,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn facts() -> Kind {
+        Kind::new("facts").unwrap()
+    }
+
+    #[test]
+    fn normalizes_whitespace() {
+        let entry = Entry::new(facts(), 1, "  Water\tboils \n at 100 °C. ").unwrap();
+        assert_eq!(entry.text, "Water boils at 100 °C.");
+        assert_eq!(
+            Entry::new(facts(), 1, " \n "),
+            Err(ValidationError::EmptyText)
+        );
+    }
+
+    #[test]
+    fn validates() {
+        let mut entry = Entry::new(facts(), 1, "A fact.").unwrap();
+        entry.text = "A  fact.".to_owned();
+        assert_eq!(entry.validate(), Err(ValidationError::Whitespace));
+
+        entry.text = "x".repeat(MAX_TEXT_CHARS + 1);
+        assert!(matches!(
+            entry.validate(),
+            Err(ValidationError::TextTooLong { .. })
+        ));
+
+        entry.text = "A fact.".to_owned();
+        entry
+            .metadata
+            .insert("source url".to_owned(), "x".to_owned());
+        assert!(matches!(
+            entry.validate(),
+            Err(ValidationError::MetadataKey(_))
+        ));
+
+        entry.metadata.clear();
+        entry.tags = vec!["health".to_owned(), "Sleep".to_owned()];
+        assert_eq!(
+            entry.validate(),
+            Err(ValidationError::Tag("Sleep".to_owned()))
+        );
+        entry.tags = vec!["sleep".to_owned(), "health".to_owned()];
+        assert_eq!(entry.validate(), Err(ValidationError::UnsortedTags));
+        entry.normalize().unwrap();
+        assert_eq!(entry.tags, ["health", "sleep"]);
+
+        assert!(Kind::new("facts_2").is_ok());
+        assert!(Kind::new("Facts").is_err());
+        assert!(Kind::new("2facts").is_err());
+        assert!(Kind::new("").is_err());
+    }
+
+    #[test]
+    fn serde_keeps_the_stored_format() {
+        let entry: Entry = serde_json::from_value(json!({
+            "kind": "facts",
+            "id": 3,
+            "title": "A fact.",
+        }))
+        .unwrap();
+        assert_eq!(
+            serde_json::to_value(&entry).unwrap(),
+            json!({ "kind": "facts", "id": 3, "title": "A fact." })
+        );
+        assert!(serde_json::from_value::<Entry>(json!({
+            "kind": "Facts",
+            "id": 3,
+            "title": "A fact.",
+        }))
+        .is_err());
+
+        let data: DataFile = serde_json::from_value(json!({
+            "facts": ["One.", {"title": "Two.", "tags": ["Science", "physics"]}],
+            "principles": [],
+        }))
+        .unwrap();
+        let facts = data.entries(&facts()).unwrap();
+        assert_eq!((facts[1].id, facts[1].text.as_str()), (2, "Two."));
+        assert_eq!(facts[1].tags, ["physics", "science"]);
+        assert!(facts[1].has_tag("science") && !facts[0].has_tag("science"));
+
+        let both: TagFilter = "physics,Science".parse().unwrap();
+        let either: TagFilter = "physics|chemistry".parse().unwrap();
+        assert!(both.matches(&facts[1]) && either.matches(&facts[1]));
+        assert!(!both.matches(&facts[0]) && !either.matches(&facts[0]));
+        assert!("physics|chemistry,biology".parse::<TagFilter>().is_err());
+        assert!(data
+            .entries(&Kind::new("quotes").unwrap())
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,93 @@
+//! `admin` creates admin accounts, for `dio-server admin <username>`.
+//!
+//! Accounts registered through `POST /users` are readers, so the first admin
+//! has to come from whoever runs the server. Later ones can also be promoted
+//! through `PUT /admin/users/{username}/role`.
+
+use crate::{
+    auth::{self, AuthSettings},
+    db::DioStore,
+    model::{Role, User},
+};
+use anyhow::{bail, Context};
+use chrono::{Timelike, Utc};
+use std::io::BufRead;
+
+/// Creates the admin `username`, with the password read from the first line
+/// of `input`. With `promote`, an existing account is made admin instead,
+/// keeping its password.
+pub async fn run(
+    store: &dyn DioStore,
+    settings: &AuthSettings,
+    username: &str,
+    promote: bool,
+    input: impl BufRead,
+) -> anyhow::Result<String> {
+    if let Some(mut user) = store.users().get(username).await? {
+        if !promote {
+            bail!(
+                "The user {username} already exists. Pass --promote if it is yours, \
+                 since anyone may have registered it"
+            );
+        }
+        user.role = Role::Admin;
+        store.users().replace(user).await?;
+        return Ok(format!("{username} is now an admin"));
+    }
+    if promote {
+        bail!("No user {username} to promote");
+    }
+
+    let password = (input.lines().next())
+        .context("Expected the password on standard input")?
+        .context("Failed to read the password")?;
+    auth::check_credentials(username, &password)?;
+    let user = User {
+        username: username.to_owned(),
+        password_hash: auth::hash_password(&password, settings)?,
+        role: Role::Admin,
+        created_at: Utc::now().with_nanosecond(0).unwrap(),
+    };
+    store.users().insert(user).await?;
+    Ok(format!("Created the admin {username}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::MemoryStore;
+
+    #[actix_web::test]
+    async fn creates_or_promotes_admins() {
+        let store = MemoryStore::default();
+        let settings = AuthSettings {
+            memory_kib: 8,
+            iterations: 1,
+            parallelism: 1,
+            session_ttl: chrono::Duration::hours(1),
+        };
+        let password = "analytical\nignored\n".as_bytes();
+
+        run(&store, &settings, "ada", false, password)
+            .await
+            .unwrap();
+        let ada = store.users().get("ada").await.unwrap().unwrap();
+        assert_eq!(ada.role, Role::Admin);
+        assert!(auth::verify_password(&ada.password_hash, "analytical"));
+        assert!(run(&store, &settings, "ada", false, password)
+            .await
+            .is_err());
+        assert!(run(&store, &settings, "bo", false, password).await.is_err());
+        assert!(run(&store, &settings, "bob", true, password).await.is_err());
+
+        let bob = User {
+            role: Role::Reader,
+            username: "bob".to_owned(),
+            ..ada
+        };
+        store.users().insert(bob).await.unwrap();
+        run(&store, &settings, "bob", true, &b""[..]).await.unwrap();
+        let bob = store.users().get("bob").await.unwrap().unwrap();
+        assert_eq!(bob.role, Role::Admin);
+    }
+}
@@ -0,0 +1,329 @@
+//! `auth` hashes passwords and resolves bearer tokens.
+//!
+//! Passwords are hashed with Argon2id. Logging in issues a random bearer token;
+//! only its SHA-256 digest is stored, so a leaked sessions collection cannot be
+//! replayed. Machine clients use API keys instead, stored the same way.
+//!
+//! [`Authenticate`] resolves the `Authorization: Bearer` header of every request
+//! into an [`Identity`] and its [`Role`]. Routes that need more than anonymous
+//! access are wrapped in [`Require`] in `route::config`.
+
+use crate::{
+    db::DioStore,
+    error::DioError,
+    model::{ApiKey, Role, Scope, Session, User},
+};
+use actix_web::{
+    body::EitherBody,
+    dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header,
+    web, FromRequest, HttpMessage, HttpRequest,
+};
+use argon2::{Config, ThreadMode, Variant, Version};
+use chrono::{Duration, Utc};
+use futures::future::{ready, LocalBoxFuture, Ready};
+use rand::RngCore;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::rc::Rc;
+
+/// Prefix of API key tokens, which tells them apart from session tokens.
+const API_KEY_PREFIX: &str = "dio_";
+
+/// Argon2id cost parameters and session lifetime, from the `argon2_*` and
+/// `session_ttl_hours` settings of `Config`.
+#[derive(Clone, Debug)]
+pub struct AuthSettings {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+    pub session_ttl: Duration,
+}
+
+impl AuthSettings {
+    fn argon2_config(&self) -> Config<'static> {
+        Config {
+            variant: Variant::Argon2id,
+            version: Version::Version13,
+            mem_cost: self.memory_kib,
+            time_cost: self.iterations,
+            lanes: self.parallelism,
+            thread_mode: ThreadMode::Sequential,
+            hash_length: 32,
+            ..Config::default()
+        }
+    }
+}
+
+/// Checks the username and password of a new account.
+pub fn check_credentials(username: &str, password: &str) -> Result<(), DioError> {
+    let valid_username = (3..=32).contains(&username.len())
+        && (username.chars()).all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if !valid_username {
+        return Err(DioError::Validation(
+            "Usernames are 3 to 32 letters, digits, `_` or `-`".to_owned(),
+        ));
+    }
+    if password.chars().count() < 8 {
+        return Err(DioError::Validation(
+            "Passwords are at least 8 characters".to_owned(),
+        ));
+    }
+    Ok(())
+}
+
+/// Hashes `password` with a fresh random salt. Slow by design, so run it off
+/// the async workers.
+pub fn hash_password(password: &str, settings: &AuthSettings) -> anyhow::Result<String> {
+    let mut salt = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut salt);
+    Ok(argon2::hash_encoded(
+        password.as_bytes(),
+        &salt,
+        &settings.argon2_config(),
+    )?)
+}
+
+/// Checks `password` against a hash from [`hash_password`], using the cost
+/// parameters recorded in the hash rather than the current settings.
+pub fn verify_password(password_hash: &str, password: &str) -> bool {
+    argon2::verify_encoded(password_hash, password.as_bytes()).unwrap_or(false)
+}
+
+/// Hash of a random password nobody knows, with the default cost parameters.
+const DUMMY_HASH: &str =
+    "$argon2id$v=19$m=19456,t=2,p=1$swjAb2l0pLg5sqGMlO5dow$K8Je/A+ahdUZKn5zEKHPYzUbasTAMuj7GbTzmymCuGc";
+
+/// Fails like [`verify_password`] on a wrong password, and takes about as long,
+/// so that logins for unknown usernames do not reveal that they are unknown.
+pub fn verify_dummy(password: &str) -> bool {
+    verify_password(DUMMY_HASH, password)
+}
+
+/// A new random bearer token, hex encoded.
+pub fn new_token() -> String {
+    let mut token = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut token);
+    hex::encode(token)
+}
+
+/// What is stored in place of `token`.
+pub fn token_digest(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// The token of an `Authorization: Bearer <token>` header.
+pub fn bearer_token(req: &HttpRequest) -> Option<&str> {
+    let value = req.headers().get(header::AUTHORIZATION)?.to_str().ok()?;
+    value.strip_prefix("Bearer ").map(str::trim)
+}
+
+/// A new API key: its id and the whole token, `dio_<id>_<secret>`.
+pub fn new_api_key() -> (String, String) {
+    let mut id = [0u8; 6];
+    rand::thread_rng().fill_bytes(&mut id);
+    let id = hex::encode(id);
+    let token = format!("{API_KEY_PREFIX}{id}_{}", new_token());
+    (id, token)
+}
+
+/// Who sent a request, resolved by [`Authenticate`].
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Identity {
+    User {
+        username: String,
+        role: Role,
+    },
+    ApiKey {
+        id: String,
+        name: String,
+        scope: Scope,
+    },
+}
+
+impl Identity {
+    /// The username, or `key:<id>` for API keys.
+    pub fn subject(&self) -> String {
+        match self {
+            Identity::User { username, .. } => username.clone(),
+            Identity::ApiKey { id, .. } => format!("key:{id}"),
+        }
+    }
+
+    pub fn role(&self) -> Role {
+        match self {
+            Identity::User { role, .. } => *role,
+            Identity::ApiKey { scope, .. } => scope.role(),
+        }
+    }
+}
+
+/// The identity behind `token`, or `None` if it is unknown, expired or its
+/// user was deleted.
+async fn resolve(store: &dyn DioStore, token: &str) -> anyhow::Result<Option<Identity>> {
+    let digest = token_digest(token);
+    if let Some(rest) = token.strip_prefix(API_KEY_PREFIX) {
+        let Some((id, _secret)) = rest.split_once('_') else {
+            return Ok(None);
+        };
+        let key: Option<ApiKey> = store.api_keys().get(id).await?;
+        return Ok(key
+            .filter(|key| key.token_digest == digest)
+            .map(|key| Identity::ApiKey {
+                id: key.id,
+                name: key.name,
+                scope: key.scope,
+            }));
+    }
+
+    let session: Option<Session> = store.sessions().get(&digest).await?;
+    let session = match session {
+        Some(session) if session.expires_at > Utc::now() => session,
+        Some(_) => {
+            store.sessions().remove(&digest).await?;
+            return Ok(None);
+        }
+        None => return Ok(None),
+    };
+    let user: Option<User> = store.users().get(&session.username).await?;
+    Ok(user.map(|user| Identity::User {
+        username: user.username,
+        role: user.role,
+    }))
+}
+
+/// Middleware that stores the [`Identity`] of the request's bearer token in the
+/// request extensions. Requests without a token pass through anonymously;
+/// requests with an unknown or expired one are rejected with `401 Unauthorized`.
+pub struct Authenticate;
+
+impl<S, B> Transform<S, ServiceRequest> for Authenticate
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Transform = AuthenticateMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(AuthenticateMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct AuthenticateMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for AuthenticateMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        Box::pin(async move {
+            let Some(token) = bearer_token(req.request()).map(str::to_owned) else {
+                return Ok(service.call(req).await?.map_into_left_body());
+            };
+            let store = (req.app_data::<web::Data<dyn DioStore>>().cloned())
+                .expect("a DioStore is registered as app data");
+
+            let rejection = match resolve(store.get_ref(), &token).await {
+                Ok(Some(identity)) => {
+                    req.extensions_mut().insert(identity);
+                    return Ok(service.call(req).await?.map_into_left_body());
+                }
+                Ok(None) => DioError::Unauthorized("Invalid or expired bearer token".to_owned()),
+                Err(err) => DioError::from(err),
+            };
+            Ok(req.error_response(rejection).map_into_right_body())
+        })
+    }
+}
+
+fn anonymous() -> DioError {
+    DioError::Unauthorized("Sign in or send an API key to do this".to_owned())
+}
+
+/// Extracts the identity of an authenticated request.
+#[derive(Clone, Debug)]
+pub struct SignedIn(pub Identity);
+
+impl FromRequest for SignedIn {
+    type Error = DioError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let identity = req.extensions().get::<Identity>().cloned();
+        ready(identity.map(SignedIn).ok_or_else(anonymous))
+    }
+}
+
+/// Route middleware that lets through requests whose [`Identity`] has at least
+/// the given role. Anonymous requests get `401 Unauthorized` and the rest
+/// `403 Forbidden`.
+pub struct Require(pub Role);
+
+impl<S, B> Transform<S, ServiceRequest> for Require
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Transform = RequireMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequireMiddleware {
+            service: Rc::new(service),
+            role: self.0,
+        }))
+    }
+}
+
+pub struct RequireMiddleware<S> {
+    service: Rc<S>,
+    role: Role,
+}
+
+impl<S, B> Service<ServiceRequest> for RequireMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let required = self.role;
+        Box::pin(async move {
+            let role = req.extensions().get::<Identity>().map(Identity::role);
+            let rejection = match role {
+                Some(role) if role >= required => {
+                    return Ok(service.call(req).await?.map_into_left_body());
+                }
+                Some(role) => DioError::Forbidden { required, role },
+                None => anonymous(),
+            };
+            Ok(req.error_response(rejection).map_into_right_body())
+        })
+    }
+}
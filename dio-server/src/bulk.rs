@@ -0,0 +1,323 @@
+//! `bulk` reads and writes whole collections as JSON arrays, NDJSON or CSV,
+//! for `POST /import` and `GET /export`.
+//!
+//! Imports are parsed into one JSON object per record, so that they are
+//! validated the same way as the body of a single create. CSV files have a
+//! header row naming the fields of each column.
+
+use crate::{error::DioError, model::Record};
+use actix_web::{web::Bytes, HttpResponse, HttpResponseBuilder};
+use futures::{
+    future,
+    stream::{self, BoxStream, Stream, StreamExt, TryStreamExt},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::convert::Infallible;
+use utoipa::ToSchema;
+
+pub const JSON: &str = "application/json";
+pub const NDJSON: &str = "application/x-ndjson";
+pub const CSV: &str = "text/csv";
+
+/// Columns of exported CSV files, in order.
+const CSV_COLUMNS: [&str; 6] = [
+    "id",
+    "title",
+    "tags",
+    "metadata",
+    "created_at",
+    "updated_at",
+];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// One JSON array of records.
+    #[default]
+    Json,
+    /// One JSON record per line.
+    Ndjson,
+    /// A header row, then one record per row.
+    Csv,
+}
+
+impl Format {
+    /// The format of a body sent as `media_type`, parameters left out.
+    pub fn from_media_type(media_type: &str) -> Result<Self, DioError> {
+        match media_type {
+            JSON => Ok(Format::Json),
+            NDJSON => Ok(Format::Ndjson),
+            CSV => Ok(Format::Csv),
+            _ => Err(DioError::Validation(format!(
+                "Unsupported Content-Type `{media_type}`. Send {JSON}, {NDJSON} or {CSV}"
+            ))),
+        }
+    }
+
+    pub fn media_type(self) -> &'static str {
+        match self {
+            Format::Json => JSON,
+            Format::Ndjson => NDJSON,
+            Format::Csv => CSV,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::Ndjson => "ndjson",
+            Format::Csv => "csv",
+        }
+    }
+}
+
+/// Why one record of an import was rejected.
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct RowError {
+    /// Position of the record in the file, from 1. Blank lines and the CSV
+    /// header are not counted.
+    pub row: usize,
+
+    pub error: String,
+}
+
+/// The records of `body`, each an object or why it is not one. Fails as a
+/// whole only if the file cannot be split into records.
+pub fn parse(format: Format, body: &[u8]) -> Result<Vec<Result<Value, String>>, DioError> {
+    match format {
+        Format::Json => {
+            let rows: Vec<Value> = serde_json::from_slice(body).map_err(|err| {
+                DioError::Validation(format!("Expected a JSON array of records: {err}"))
+            })?;
+            Ok(rows.into_iter().map(object).collect())
+        }
+        Format::Ndjson => {
+            let body = std::str::from_utf8(body)
+                .map_err(|err| DioError::Validation(format!("Invalid UTF-8: {err}")))?;
+            Ok((body.lines())
+                .filter(|line| !line.trim().is_empty())
+                .map(|line| match serde_json::from_str(line) {
+                    Ok(row) => object(row),
+                    Err(err) => Err(format!("Invalid JSON: {err}")),
+                })
+                .collect())
+        }
+        Format::Csv => parse_csv(body),
+    }
+}
+
+fn object(row: Value) -> Result<Value, String> {
+    match row {
+        Value::Object(_) => Ok(row),
+        _ => Err("Expected a JSON object".to_owned()),
+    }
+}
+
+/// Empty cells are left out of their record. `id` cells holding an integer
+/// become JSON numbers, `tags` cells arrays of their space-separated words,
+/// `metadata` cells the JSON object they hold, every other cell a string.
+fn parse_csv(body: &[u8]) -> Result<Vec<Result<Value, String>>, DioError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(body);
+    let headers = (reader.headers().cloned())
+        .map_err(|err| DioError::Validation(format!("Invalid CSV header: {err}")))?;
+
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        rows.push(match record {
+            Ok(record) => csv_record(&headers, &record),
+            Err(err) => Err(format!("Invalid CSV: {err}")),
+        });
+    }
+    Ok(rows)
+}
+
+/// One CSV row as an object, its cells keyed by the `headers` above them.
+fn csv_record(headers: &csv::StringRecord, record: &csv::StringRecord) -> Result<Value, String> {
+    let mut row = Map::new();
+    for (field, cell) in headers.iter().zip(record.iter()) {
+        if cell.is_empty() {
+            continue;
+        }
+        let value = match (field, cell.parse::<i64>()) {
+            ("id", Ok(id)) => id.into(),
+            ("tags", _) => cell.split_whitespace().collect::<Vec<_>>().into(),
+            ("metadata", _) => serde_json::from_str(cell)
+                .map_err(|err| format!("Invalid JSON in `metadata`: {err}"))?,
+            _ => cell.into(),
+        };
+        row.insert(field.to_owned(), value);
+    }
+    Ok(Value::Object(row))
+}
+
+/// Responds with `records` written out in `format`. JSON arrays are loaded in
+/// full first, so a storage error still gets an error status. NDJSON and CSV
+/// are streamed, and the status is sent by the time a record fails to load, so
+/// they end with a line saying so instead, see [`error_line`].
+pub async fn respond<T: Record>(
+    mut res: HttpResponseBuilder,
+    format: Format,
+    records: BoxStream<'static, anyhow::Result<T>>,
+) -> Result<HttpResponse, DioError> {
+    res.content_type(format.media_type());
+    if format == Format::Json {
+        let records: Vec<T> = records.try_collect().await?;
+        let body = serde_json::to_vec(&records).map_err(anyhow::Error::from)?;
+        return Ok(res.body(body));
+    }
+    Ok(res.streaming(lines(format, records)))
+}
+
+/// `records` as NDJSON or CSV lines, ending with an [`error_line`] if one
+/// fails to load.
+fn lines<T: Record>(
+    format: Format,
+    records: BoxStream<'static, anyhow::Result<T>>,
+) -> impl Stream<Item = Result<Bytes, Infallible>> {
+    let head = match format {
+        Format::Csv => format!("{}\n", CSV_COLUMNS.join(",")),
+        Format::Json | Format::Ndjson => String::new(),
+    };
+    let body = records.scan(false, move |failed, record| {
+        if *failed {
+            return future::ready(None);
+        }
+        let chunk = record
+            .and_then(|record| encode_one(format, &record))
+            .unwrap_or_else(|err| {
+                tracing::error!(error = format!("{err:#}"), "ending export stream early");
+                *failed = true;
+                error_line(format, DioError::from(err))
+            });
+        future::ready(Some(Ok(Bytes::from(chunk))))
+    });
+    (stream::once(future::ready(Ok(Bytes::from(head)))).chain(body))
+        .filter(|chunk| future::ready(chunk.as_ref().is_ok_and(|chunk| !chunk.is_empty())))
+}
+
+/// Last line of a stream cut short by `err`. In NDJSON it is an object with
+/// an `error` member and no `id`, in CSV a row with `error` in its `id` column
+/// and the message as its `title`.
+fn error_line(format: Format, err: DioError) -> Vec<u8> {
+    let message = err.detail();
+    match format {
+        Format::Csv => {
+            let mut row = vec![String::new(); CSV_COLUMNS.len()];
+            row[0] = "error".to_owned();
+            row[1] = message;
+            let mut writer = csv::WriterBuilder::new()
+                .has_headers(false)
+                .from_writer(Vec::new());
+            writer.write_record(row).expect("writes to a Vec succeed");
+            writer.into_inner().expect("writes to a Vec succeed")
+        }
+        Format::Json | Format::Ndjson => format!("{}\n", json!({ "error": message })).into_bytes(),
+    }
+}
+
+/// `record` as one NDJSON or CSV line.
+fn encode_one<T: Record>(format: Format, record: &T) -> anyhow::Result<Vec<u8>> {
+    Ok(match format {
+        Format::Json | Format::Ndjson => {
+            let mut line = serde_json::to_vec(record)?;
+            line.push(b'\n');
+            line
+        }
+        Format::Csv => csv_row(record)?,
+    })
+}
+
+/// The [`CSV_COLUMNS`] of `record`, as one CSV row.
+fn csv_row<T: Record>(record: &T) -> anyhow::Result<Vec<u8>> {
+    let fields = serde_json::to_value(record)?;
+    let cells = CSV_COLUMNS.map(|column| match &fields[column] {
+        Value::Null => String::new(),
+        Value::String(cell) => cell.clone(),
+        Value::Array(items) => (items.iter())
+            .filter_map(Value::as_str)
+            .collect::<Vec<_>>()
+            .join(" "),
+        cell => cell.to_string(),
+    });
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(Vec::new());
+    writer.write_record(cells)?;
+    Ok(writer.into_inner()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{Entry, Kind};
+    use actix_web::body;
+
+    #[test]
+    fn parses_csv_cells() {
+        let body = br#"id,title,tags,metadata,created_at
+7,"Cats purr, mostly",,,
+x, Dogs bark ,pets  loud,"{""source"": ""vet""}",2024-01-02T00:00:00Z
+9,Owls hoot,,{source},
+8,"Birds sing
+"#;
+        let rows = parse_csv(body).unwrap();
+        assert_eq!(rows[0], Ok(json!({"id": 7, "title": "Cats purr, mostly"})));
+        assert_eq!(
+            rows[1],
+            Ok(json!({
+                "id": "x",
+                "title": "Dogs bark",
+                "tags": ["pets", "loud"],
+                "metadata": {"source": "vet"},
+                "created_at": "2024-01-02T00:00:00Z",
+            }))
+        );
+        assert!(rows[2]
+            .as_ref()
+            .unwrap_err()
+            .starts_with("Invalid JSON in `metadata`"));
+        assert!(rows[3].as_ref().unwrap_err().starts_with("Invalid CSV"));
+    }
+
+    /// A fact that loads, one that does not, and one that is never reached.
+    fn failing_records() -> BoxStream<'static, anyhow::Result<Entry>> {
+        let kind = Kind::new("facts").unwrap();
+        let fact = |id, title| Entry::new(kind.clone(), id, title).unwrap();
+        let records = [
+            Ok(fact(1, "Cats purr")),
+            Err(anyhow::anyhow!("disk gone")),
+            Ok(fact(2, "Dogs bark")),
+        ];
+        stream::iter(records).boxed()
+    }
+
+    async fn respond_with(format: Format) -> Result<Bytes, DioError> {
+        let res = respond(HttpResponse::Ok(), format, failing_records()).await?;
+        Ok(body::to_bytes(res.into_body()).await.unwrap())
+    }
+
+    #[actix_web::test]
+    async fn ends_streams_with_an_error_line() {
+        let detail = "The database could not complete the request";
+        assert_eq!(
+            respond_with(Format::Ndjson).await.unwrap(),
+            format!("{{\"kind\":\"facts\",\"id\":1,\"title\":\"Cats purr\"}}\n{{\"error\":\"{detail}\"}}\n")
+        );
+        assert_eq!(
+            respond_with(Format::Csv).await.unwrap(),
+            format!("id,title,tags,metadata,created_at,updated_at\n1,Cats purr,,,,\nerror,{detail},,,,\n")
+        );
+    }
+
+    #[actix_web::test]
+    async fn fails_json_exports_as_a_whole() {
+        let err = respond_with(Format::Json).await.unwrap_err();
+        assert!(matches!(err, DioError::Storage(_)), "{err:?}");
+        let res = respond::<Entry>(HttpResponse::Ok(), Format::Json, stream::empty().boxed());
+        let body = res.await.unwrap().into_body();
+        assert_eq!(body::to_bytes(body).await.unwrap(), "[]");
+    }
+}
@@ -0,0 +1,689 @@
+//! `config` loads the server settings.
+//!
+//! Each layer overrides the one before it:
+//!
+//! 1. built-in defaults,
+//! 2. a TOML file, `--config <file>`, `DIO_CONFIG` or `./dio.toml` if it exists,
+//! 3. `DIO_*` environment variables, e.g. `DIO_PORT` or `DIO_COLLECTIONS_FACTS`,
+//! 4. command-line flags.
+//!
+//! Every invalid key of every layer is reported at once.
+
+use crate::{auth::AuthSettings, db::kinds, error::DioError, model::Kind, ratelimit::Budget};
+use dio_server::{
+    COLL_NAME_API_KEYS, COLL_NAME_COUNTERS, COLL_NAME_KINDS, COLL_NAME_SESSIONS, COLL_NAME_USERS,
+    DB_NAME, KINDS,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    env, fmt, fs,
+    path::Path,
+    str::FromStr,
+    time::Duration,
+};
+
+/// Read when no file is named explicitly, if it exists.
+const DEFAULT_FILE: &str = "dio.toml";
+
+/// Every setting, by its dotted key in the config file, except the
+/// `collections.<kind>` of entry kinds.
+const KEYS: [&str; 23] = [
+    "bind",
+    "port",
+    "workers",
+    "shutdown_timeout_secs",
+    "database_url",
+    "db_name",
+    "connect_timeout_secs",
+    "log_level",
+    "log_format",
+    "kinds",
+    "argon2_memory_kib",
+    "argon2_iterations",
+    "argon2_parallelism",
+    "session_ttl_hours",
+    "rate_read_burst",
+    "rate_read_per_second",
+    "rate_write_burst",
+    "rate_write_per_second",
+    "collections.users",
+    "collections.sessions",
+    "collections.api_keys",
+    "collections.kinds",
+    "collections.counters",
+];
+
+/// Flags overriding the config file and environment.
+#[derive(clap::Args, Debug, Default)]
+pub struct ConfigArgs {
+    /// TOML config file. Defaults to `DIO_CONFIG`, then `./dio.toml` if it exists.
+    #[arg(long, value_name = "FILE")]
+    pub config: Option<String>,
+
+    /// Address to listen on.
+    #[arg(long)]
+    pub bind: Option<String>,
+
+    #[arg(long)]
+    pub port: Option<String>,
+
+    /// Worker threads. Defaults to one per physical CPU core.
+    #[arg(long)]
+    pub workers: Option<String>,
+
+    /// How long to let in-flight requests finish on SIGTERM or SIGINT.
+    #[arg(long, value_name = "SECONDS")]
+    pub shutdown_timeout_secs: Option<String>,
+
+    /// `mongodb://...`, `sqlite://path` or `memory://`.
+    #[arg(long)]
+    pub database_url: Option<String>,
+
+    /// MongoDB database name.
+    #[arg(long)]
+    pub db_name: Option<String>,
+
+    /// How long to wait at startup for the database to answer.
+    #[arg(long, value_name = "SECONDS")]
+    pub connect_timeout_secs: Option<String>,
+
+    /// `error`, `warn`, `info`, `debug` or `trace`.
+    #[arg(long)]
+    pub log_level: Option<String>,
+
+    /// `pretty` for people or `json` for log collectors.
+    #[arg(long)]
+    pub log_format: Option<String>,
+
+    /// Entry kinds to serve, comma-separated. More can be registered through
+    /// `POST /kinds`.
+    #[arg(long, value_name = "KIND,...")]
+    pub kinds: Option<String>,
+
+    /// Argon2id memory cost of new password hashes.
+    #[arg(long, value_name = "KIB")]
+    pub argon2_memory_kib: Option<String>,
+
+    /// Argon2id passes over memory.
+    #[arg(long)]
+    pub argon2_iterations: Option<String>,
+
+    /// Argon2id lanes.
+    #[arg(long)]
+    pub argon2_parallelism: Option<String>,
+
+    /// How long logins last.
+    #[arg(long, value_name = "HOURS")]
+    pub session_ttl_hours: Option<String>,
+
+    /// Reads a client may make at once. 0 turns read limits off.
+    #[arg(long)]
+    pub rate_read_burst: Option<String>,
+
+    /// Reads a client regains each second.
+    #[arg(long)]
+    pub rate_read_per_second: Option<String>,
+
+    /// Writes a client may make at once. 0 turns write limits off.
+    #[arg(long)]
+    pub rate_write_burst: Option<String>,
+
+    /// Writes a client regains each second.
+    #[arg(long)]
+    pub rate_write_per_second: Option<String>,
+
+    /// Collection or table name, e.g. `--collection facts=dio_facts`. Repeatable.
+    #[arg(long, value_name = "KIND=NAME")]
+    pub collection: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl FromStr for LogLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "error" => Ok(LogLevel::Error),
+            "warn" => Ok(LogLevel::Warn),
+            "info" => Ok(LogLevel::Info),
+            "debug" => Ok(LogLevel::Debug),
+            "trace" => Ok(LogLevel::Trace),
+            _ => Err(format!(
+                "unknown log level `{s}`, expected error, warn, info, debug or trace"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+            LogLevel::Trace => "trace",
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    Pretty,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "pretty" => Ok(LogFormat::Pretty),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format `{s}`, expected pretty or json")),
+        }
+    }
+}
+
+/// Names of the collections, or tables in SQLite.
+#[derive(Clone, Debug)]
+pub struct Collections {
+    pub users: String,
+    pub sessions: String,
+    pub api_keys: String,
+    /// Holds the kinds registered through `POST /kinds`.
+    pub kinds: String,
+    /// Holds the last id handed out per collection.
+    pub counters: String,
+    /// Collections of the entry kinds not kept under their own name.
+    pub entries: BTreeMap<Kind, String>,
+}
+
+impl Collections {
+    /// The collection of the entries of `kind`.
+    pub fn of<'a>(&'a self, kind: &'a Kind) -> &'a str {
+        self.entries.get(kind).map_or(kind.as_str(), String::as_str)
+    }
+
+    /// The collections holding something other than entries.
+    pub fn reserved(&self) -> [&str; 5] {
+        [
+            &self.users,
+            &self.sessions,
+            &self.api_keys,
+            &self.kinds,
+            &self.counters,
+        ]
+    }
+}
+
+impl Default for Collections {
+    fn default() -> Self {
+        Self {
+            users: COLL_NAME_USERS.to_owned(),
+            sessions: COLL_NAME_SESSIONS.to_owned(),
+            api_keys: COLL_NAME_API_KEYS.to_owned(),
+            kinds: COLL_NAME_KINDS.to_owned(),
+            counters: COLL_NAME_COUNTERS.to_owned(),
+            entries: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub bind: String,
+    pub port: u16,
+    /// `None` leaves it to actix-web.
+    pub workers: Option<usize>,
+    /// How long to let in-flight requests finish on SIGTERM or SIGINT.
+    pub shutdown_timeout: Duration,
+    pub database_url: String,
+    pub db_name: String,
+    /// How long to wait at startup for the database to answer.
+    pub connect_timeout: Duration,
+    /// Entry kinds served from the start, on top of those registered through
+    /// the API.
+    pub kinds: Vec<Kind>,
+    pub collections: Collections,
+    pub log_level: LogLevel,
+    pub log_format: LogFormat,
+    pub auth: AuthSettings,
+    /// Rate limits of reads, per client.
+    pub rate_read: Budget,
+    /// Rate limits of everything else, per client.
+    pub rate_write: Budget,
+}
+
+/// [`Config`] while its layers are applied.
+struct Builder {
+    bind: String,
+    port: u16,
+    workers: Option<usize>,
+    shutdown_timeout: Duration,
+    database_url: Option<String>,
+    db_name: String,
+    connect_timeout: Duration,
+    kinds: Vec<Kind>,
+    collections: Collections,
+    log_level: LogLevel,
+    log_format: LogFormat,
+    auth: AuthSettings,
+    rate_read: Budget,
+    rate_write: Budget,
+    /// Every problem found so far, with where it came from.
+    errors: Vec<String>,
+}
+
+impl Config {
+    pub fn load(args: &ConfigArgs) -> Result<Self, DioError> {
+        Self::resolve(args, &env::vars().collect())
+    }
+
+    /// Layers `args` over the variables of `env`, over the file they name.
+    pub(crate) fn resolve(
+        args: &ConfigArgs,
+        env: &HashMap<String, String>,
+    ) -> Result<Self, DioError> {
+        let mut builder = Builder {
+            bind: "127.0.0.1".to_owned(),
+            port: 5000,
+            workers: None,
+            shutdown_timeout: Duration::from_secs(30),
+            database_url: None,
+            db_name: DB_NAME.to_owned(),
+            connect_timeout: Duration::from_secs(30),
+            kinds: KINDS.map(|kind| kind.parse().unwrap()).to_vec(),
+            collections: Collections::default(),
+            log_level: LogLevel::Info,
+            log_format: LogFormat::Pretty,
+            auth: AuthSettings {
+                memory_kib: 19456,
+                iterations: 2,
+                parallelism: 1,
+                session_ttl: chrono::Duration::hours(168),
+            },
+            rate_read: Budget {
+                burst: 120,
+                per_second: 20.0,
+            },
+            rate_write: Budget {
+                burst: 30,
+                per_second: 1.0,
+            },
+            errors: Vec::new(),
+        };
+        builder.apply_file(
+            args.config
+                .clone()
+                .or_else(|| env.get("DIO_CONFIG").cloned()),
+        );
+        builder.apply_env(env);
+        builder.apply_args(args);
+        builder.build(env)
+    }
+}
+
+impl Builder {
+    fn apply_file(&mut self, path: Option<String>) {
+        let path = match path {
+            Some(path) => path,
+            None if Path::new(DEFAULT_FILE).exists() => DEFAULT_FILE.to_owned(),
+            None => return,
+        };
+        let table = match fs::read_to_string(&path) {
+            Ok(text) => match text.parse::<toml::Value>() {
+                Ok(toml::Value::Table(table)) => table,
+                Ok(_) => unreachable!("TOML documents are tables"),
+                Err(e) => return self.errors.push(format!("{path}: {e}")),
+            },
+            Err(e) => return self.errors.push(format!("{path}: {e}")),
+        };
+
+        let mut entries = Vec::new();
+        for (key, value) in table {
+            match value {
+                toml::Value::Table(nested) if key == "collections" => {
+                    entries.extend(nested.into_iter().map(|(k, v)| (format!("{key}.{k}"), v)))
+                }
+                value => entries.push((key, value)),
+            }
+        }
+        for (key, value) in entries {
+            let value = match value {
+                toml::Value::String(value) => value,
+                toml::Value::Integer(value) => value.to_string(),
+                toml::Value::Float(value) => value.to_string(),
+                toml::Value::Array(items) if key == "kinds" => {
+                    match items
+                        .iter()
+                        .map(toml::Value::as_str)
+                        .collect::<Option<Vec<_>>>()
+                    {
+                        Some(kinds) => kinds.join(","),
+                        None => {
+                            self.errors
+                                .push(format!("{path}: `{key}`: expected a list of strings"));
+                            continue;
+                        }
+                    }
+                }
+                _ => {
+                    self.errors
+                        .push(format!("{path}: `{key}`: expected a string or a number"));
+                    continue;
+                }
+            };
+            self.set(&format!("{path}: `{key}`"), &key, &value);
+        }
+    }
+
+    fn apply_env(&mut self, env: &HashMap<String, String>) {
+        for key in KEYS {
+            let var = format!("DIO_{}", key.replace('.', "_").to_ascii_uppercase());
+            if let Some(value) = env.get(&var) {
+                self.set(&var, key, value);
+            }
+        }
+        // Collections of entry kinds, which are not known in advance.
+        for (var, value) in env {
+            let Some(kind) = var.strip_prefix("DIO_COLLECTIONS_") else {
+                continue;
+            };
+            let key = format!("collections.{}", kind.to_ascii_lowercase());
+            if !KEYS.contains(&key.as_str()) {
+                self.set(var, &key, value);
+            }
+        }
+    }
+
+    fn apply_args(&mut self, args: &ConfigArgs) {
+        let flags = [
+            ("bind", &args.bind),
+            ("port", &args.port),
+            ("workers", &args.workers),
+            ("shutdown_timeout_secs", &args.shutdown_timeout_secs),
+            ("database_url", &args.database_url),
+            ("db_name", &args.db_name),
+            ("connect_timeout_secs", &args.connect_timeout_secs),
+            ("log_level", &args.log_level),
+            ("log_format", &args.log_format),
+            ("kinds", &args.kinds),
+            ("argon2_memory_kib", &args.argon2_memory_kib),
+            ("argon2_iterations", &args.argon2_iterations),
+            ("argon2_parallelism", &args.argon2_parallelism),
+            ("session_ttl_hours", &args.session_ttl_hours),
+            ("rate_read_burst", &args.rate_read_burst),
+            ("rate_read_per_second", &args.rate_read_per_second),
+            ("rate_write_burst", &args.rate_write_burst),
+            ("rate_write_per_second", &args.rate_write_per_second),
+        ];
+        for (key, value) in flags {
+            if let Some(value) = value {
+                self.set(&format!("--{}", key.replace('_', "-")), key, value);
+            }
+        }
+        for collection in &args.collection {
+            match collection.split_once('=') {
+                Some((kind, name)) => self.set(
+                    &format!("--collection {kind}"),
+                    &format!("collections.{kind}"),
+                    name,
+                ),
+                None => self.errors.push(format!(
+                    "--collection: expected KIND=NAME, got `{collection}`"
+                )),
+            }
+        }
+    }
+
+    /// Sets `key` from `value`, or records why it cannot be, naming `source`.
+    fn set(&mut self, source: &str, key: &str, value: &str) {
+        if let Err(e) = self.try_set(key, value) {
+            self.errors.push(format!("{source}: {e}"));
+        }
+    }
+
+    fn try_set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "bind" => self.bind = value.to_owned(),
+            "port" => {
+                self.port = match value.parse() {
+                    Ok(port) if port > 0 => port,
+                    _ => return Err(format!("expected a port number, got `{value}`")),
+                }
+            }
+            "workers" => {
+                self.workers = match value.parse() {
+                    Ok(workers) if workers > 0 => Some(workers),
+                    _ => return Err(format!("expected a positive integer, got `{value}`")),
+                }
+            }
+            "shutdown_timeout_secs" => self.shutdown_timeout = Duration::from_secs(seconds(value)?),
+            "database_url" => self.database_url = Some(value.to_owned()),
+            "db_name" => self.db_name = name(value)?,
+            "connect_timeout_secs" => self.connect_timeout = Duration::from_secs(seconds(value)?),
+            "log_level" => self.log_level = value.parse()?,
+            "log_format" => self.log_format = value.parse()?,
+            "kinds" => {
+                let mut kinds = Vec::new();
+                for kind in value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|kind| !kind.is_empty())
+                {
+                    let kind: Kind = kind.parse().map_err(|e| format!("{e}"))?;
+                    if !kinds.contains(&kind) {
+                        kinds.push(kind);
+                    }
+                }
+                self.kinds = kinds;
+            }
+            "argon2_memory_kib" => self.auth.memory_kib = positive(value)?,
+            "argon2_iterations" => self.auth.iterations = positive(value)?,
+            "argon2_parallelism" => self.auth.parallelism = positive(value)?,
+            "session_ttl_hours" => {
+                self.auth.session_ttl = chrono::Duration::hours(positive(value)?.into())
+            }
+            "rate_read_burst" => self.rate_read.burst = burst(value)?,
+            "rate_read_per_second" => self.rate_read.per_second = per_second(value)?,
+            "rate_write_burst" => self.rate_write.burst = burst(value)?,
+            "rate_write_per_second" => self.rate_write.per_second = per_second(value)?,
+            _ => {
+                let collections = &mut self.collections;
+                let slot = match key.strip_prefix("collections.") {
+                    Some("users") => &mut collections.users,
+                    Some("sessions") => &mut collections.sessions,
+                    Some("api_keys") => &mut collections.api_keys,
+                    Some("kinds") => &mut collections.kinds,
+                    Some("counters") => &mut collections.counters,
+                    Some(kind) => match kind.parse::<Kind>() {
+                        Ok(kind) => {
+                            collections.entries.insert(kind, name(value)?);
+                            return Ok(());
+                        }
+                        Err(_) => return Err("unknown setting".to_owned()),
+                    },
+                    None => return Err("unknown setting".to_owned()),
+                };
+                *slot = name(value)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn build(mut self, env: &HashMap<String, String>) -> Result<Config, DioError> {
+        // Names from before the config file existed.
+        let database_url = (self.database_url.take())
+            .or_else(|| env.get("DATABASE_URL").cloned())
+            .or_else(|| env.get("MONGODB_URI").cloned());
+        if database_url.is_none() {
+            self.errors.push(
+                "`database_url` is not set. Use --database-url, DIO_DATABASE_URL or the config file"
+                    .to_owned(),
+            );
+        }
+        if self.auth.memory_kib < 8 * self.auth.parallelism {
+            self.errors.push(
+                "`argon2_memory_kib` must be at least 8 times `argon2_parallelism`".to_owned(),
+            );
+        }
+        let mut taken = Vec::from(self.collections.reserved());
+        for kind in &self.kinds {
+            let collection = self.collections.of(kind);
+            if let Err(e) = kinds::check(kind, collection, &taken) {
+                self.errors.push(format!("`kinds`: {e}"));
+            }
+            taken.push(collection);
+        }
+        match (database_url, self.errors.is_empty()) {
+            (Some(database_url), true) => Ok(Config {
+                bind: self.bind,
+                port: self.port,
+                workers: self.workers,
+                shutdown_timeout: self.shutdown_timeout,
+                database_url,
+                db_name: self.db_name,
+                connect_timeout: self.connect_timeout,
+                kinds: self.kinds,
+                collections: self.collections,
+                log_level: self.log_level,
+                log_format: self.log_format,
+                auth: self.auth,
+                rate_read: self.rate_read,
+                rate_write: self.rate_write,
+            }),
+            _ => Err(DioError::Config(format!(
+                "Invalid configuration:\n  {}",
+                self.errors.join("\n  ")
+            ))),
+        }
+    }
+}
+
+fn seconds(value: &str) -> Result<u64, String> {
+    match value.parse() {
+        Ok(secs) if secs > 0 => Ok(secs),
+        _ => Err(format!(
+            "expected a positive number of seconds, got `{value}`"
+        )),
+    }
+}
+
+fn positive(value: &str) -> Result<u32, String> {
+    match value.parse() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("expected a positive integer, got `{value}`")),
+    }
+}
+
+fn burst(value: &str) -> Result<u32, String> {
+    (value.parse()).map_err(|_| format!("expected a number of requests, got `{value}`"))
+}
+
+fn per_second(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(rate) if rate > 0.0 && rate.is_finite() => Ok(rate),
+        _ => Err(format!("expected a positive number, got `{value}`")),
+    }
+}
+
+/// Database, collection and table names end up in SQL, so they are kept to
+/// letters, digits and underscores.
+fn name(value: &str) -> Result<String, String> {
+    let valid = !value.is_empty()
+        && value.len() <= 64
+        && (value.chars()).all(|c| c.is_ascii_alphanumeric() || c == '_');
+    match valid {
+        true => Ok(value.to_owned()),
+        false => Err(format!(
+            "`{value}` must be 1 to 64 letters, digits or underscores"
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An environment of `vars` only, so tests do not see the real one.
+    fn environment(vars: &[(&str, &str)]) -> HashMap<String, String> {
+        (vars.iter())
+            .map(|(var, value)| (var.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn layers_file_env_and_flags() {
+        let path = env::temp_dir().join(format!("dio-config-{}.toml", std::process::id()));
+        let file = r#"
+            port = 6000
+            db_name = "from_file"
+            rate_read_per_second = 2.5
+            [collections]
+            facts = "file_facts"
+        "#;
+        fs::write(&path, file).unwrap();
+        let args = ConfigArgs {
+            config: Some(path.display().to_string()),
+            rate_write_burst: Some("3".to_owned()),
+            db_name: Some("from_flag".to_owned()),
+            ..ConfigArgs::default()
+        };
+        let vars = environment(&[
+            ("DATABASE_URL", "memory://"),
+            ("DIO_PORT", "7000"),
+            ("DIO_DB_NAME", "from_env"),
+            ("DIO_SESSION_TTL_HOURS", "2"),
+        ]);
+        let config = Config::resolve(&args, &vars);
+        fs::remove_file(&path).unwrap();
+        let config = config.unwrap();
+
+        assert_eq!(config.database_url, "memory://");
+        assert_eq!(config.port, 7000);
+        assert_eq!(config.db_name, "from_flag");
+        let collection = |kind: &str| config.collections.of(&kind.parse().unwrap()).to_owned();
+        assert_eq!(collection("facts"), "file_facts");
+        assert_eq!(collection("principles"), "principles");
+        assert_eq!(config.rate_read.per_second, 2.5);
+        assert_eq!(config.rate_read.burst, 120);
+        assert_eq!(config.rate_write.burst, 3);
+        assert_eq!(config.auth.session_ttl, chrono::Duration::hours(2));
+    }
+
+    #[test]
+    fn reports_every_invalid_setting() {
+        let args = ConfigArgs {
+            port: Some("0".to_owned()),
+            argon2_parallelism: Some("many".to_owned()),
+            ..ConfigArgs::default()
+        };
+        let vars = environment(&[
+            ("DIO_RATE_READ_PER_SECOND", "-1"),
+            ("DIO_COLLECTIONS_FACTS", "drop table"),
+            ("DIO_KINDS", "facts,login"),
+        ]);
+        let DioError::Config(message) = Config::resolve(&args, &vars).unwrap_err() else {
+            panic!("expected a config error");
+        };
+        for source in [
+            "--port",
+            "--argon2-parallelism",
+            "DIO_RATE_READ_PER_SECOND",
+            "DIO_COLLECTIONS_FACTS",
+            "`login` is reserved",
+            "`database_url` is not set",
+        ] {
+            assert!(message.contains(source), "{message}");
+        }
+    }
+}
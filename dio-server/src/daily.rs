@@ -0,0 +1,83 @@
+//! `daily` picks the entry of the day.
+//!
+//! Days are numbered from the Unix epoch. Each run of `n` days, where `n` is the
+//! number of entries, walks through one shuffle of the ids, so an entry only
+//! comes back once every other entry had its day. The shuffle is seeded from
+//! the run number and an optional caller seed, so every client asking about
+//! the same day gets the same entry as long as the set of ids does not change.
+//!
+//! ChaCha8 is value-stable across `rand_chacha` releases, and the shuffle is
+//! written out here rather than taken from `rand`, whose algorithms may change,
+//! so that upgrades do not change past or future picks either.
+
+use chrono::NaiveDate;
+use rand_chacha::{
+    rand_core::{RngCore, SeedableRng},
+    ChaCha8Rng,
+};
+
+/// The id to show on `date`, or `None` if there are no `ids`.
+pub fn pick(ids: &[i32], date: NaiveDate, seed: &str) -> Option<i32> {
+    if ids.is_empty() {
+        return None;
+    }
+    let mut ids = ids.to_vec();
+    ids.sort_unstable();
+
+    let epoch = NaiveDate::from_ymd_opt(1970, 1, 1).unwrap();
+    let day = date.signed_duration_since(epoch).num_days();
+    let len = ids.len() as i64;
+    let (run, position) = (day.div_euclid(len), day.rem_euclid(len));
+
+    let mut key = [0; 32];
+    key[..8].copy_from_slice(&fnv1a(seed.as_bytes()).to_le_bytes());
+    key[8..16].copy_from_slice(&run.to_le_bytes());
+    let mut rng = ChaCha8Rng::from_seed(key);
+    shuffle(&mut ids, &mut rng);
+    Some(ids[position as usize])
+}
+
+/// Fisher-Yates. The modulo bias is below 2^-32 for any realistic length.
+fn shuffle(ids: &mut [i32], rng: &mut ChaCha8Rng) {
+    for i in (1..ids.len()).rev() {
+        let j = rng.next_u64() % (i as u64 + 1);
+        ids.swap(i, j as usize);
+    }
+}
+
+/// 64-bit FNV-1a. Unlike `DefaultHasher` it is stable across Rust releases.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shows_every_id_once_per_run() {
+        let ids = [7, 3, 11, 5];
+        // Day 20000 is the first of run 5000.
+        let start = NaiveDate::from_ymd_opt(2024, 10, 4).unwrap();
+        let mut run: Vec<i32> = (0..4)
+            .map(|day| pick(&ids, start + chrono::Duration::days(day), "").unwrap())
+            .collect();
+        assert_eq!(pick(&ids, start, ""), pick(&[3, 5, 7, 11], start, ""));
+        run.sort_unstable();
+        assert_eq!(run, [3, 5, 7, 11]);
+        assert_eq!(pick(&[], start, ""), None);
+    }
+
+    #[test]
+    fn keeps_picks_across_upgrades() {
+        // Pinned, so that a dependency bump changing the picks fails here.
+        let ids: Vec<i32> = (1..=10).collect();
+        let start = NaiveDate::from_ymd_opt(2024, 10, 4).unwrap();
+        let picks: Vec<i32> = (0..6)
+            .map(|day| pick(&ids, start + chrono::Duration::days(day), "dio").unwrap())
+            .collect();
+        assert_eq!(picks, [4, 3, 10, 5, 1, 7]);
+    }
+}
@@ -1,89 +1,792 @@
 /// See https://github.com/Mr-Malomz/actix-mongo-api/blob/main/src/repository/mongodb_repo.rs.
-use super::model::{Facts, Principles};
-use crate::util::get_env_var;
-use dotenv::dotenv;
+use super::model::{ApiKey, Entry, Keyed, Kind, KindDef, Record, Session, TagCount, User};
+use crate::{config::Config, error::DioError};
+use actix_web::rt::time;
+use anyhow::{anyhow, Context};
+use async_trait::async_trait;
+use chrono::{DateTime, SecondsFormat, Utc};
+use futures::stream::{BoxStream, StreamExt, TryStreamExt};
 use mongodb::{
-    options::{ClientOptions, ResolverConfig},
-    Client, Collection,
+    bson::{doc, Document},
+    error::{ErrorKind, WriteFailure},
+    options::{
+        ClientOptions, FindOneAndUpdateOptions, FindOneOptions, FindOptions, IndexOptions,
+        ReplaceOptions, ResolverConfig, ReturnDocument, UpdateOptions,
+    },
+    Client, ClientSession, Collection, IndexModel,
 };
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+pub mod kinds;
+mod memory;
+mod metered;
+mod sqlite;
+
+pub use kinds::Kinds;
+pub use memory::MemoryStore;
+pub use metered::MeteredStore;
+pub use sqlite::SqliteStore;
+
+/// CRUD operations over one collection of [`Record`]s, keyed by `Record::id`.
+///
+/// Ids are unique per collection. Each collection keeps a counter so that
+/// [`Repo::next_id`] never hands out an id twice, even after deletes.
+#[async_trait]
+pub trait Repo<T: Record>: Send + Sync {
+    /// Readies the collection for serving once the backend answers, e.g.
+    /// creates indexes.
+    async fn prepare(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Returns the requested window of matching records and the number of
+    /// records matching overall.
+    async fn list(&self, query: &ListQuery) -> anyhow::Result<(Vec<T>, u64)>;
+
+    /// Streams every record matching `query`, ignoring its `offset` and `limit`,
+    /// without holding the whole result in memory.
+    async fn stream(
+        &self,
+        query: &ListQuery,
+    ) -> anyhow::Result<BoxStream<'static, anyhow::Result<T>>>;
+
+    async fn get(&self, id: i32) -> anyhow::Result<Option<T>>;
+
+    /// Every stored id, in ascending order.
+    async fn ids(&self) -> anyhow::Result<Vec<i32>>;
+
+    /// Number of stored records.
+    async fn count(&self) -> anyhow::Result<u64>;
+
+    /// Allocates an id greater than any id allocated or created so far.
+    async fn next_id(&self) -> anyhow::Result<i32>;
+
+    /// Fails with [`Conflict`] if a record with the same id already exists.
+    async fn create(&self, item: T) -> anyhow::Result<T>;
+
+    /// Replaces the record stored under `id`. Returns `None` if there is none.
+    async fn update(&self, id: i32, item: T) -> anyhow::Result<Option<T>>;
+
+    /// Returns `false` if no record was stored under `id`.
+    async fn delete(&self, id: i32) -> anyhow::Result<bool>;
+
+    /// Every tag in use, with the number of records carrying it, by tag.
+    async fn tags(&self) -> anyhow::Result<Vec<TagCount>>;
+
+    /// Up to `limit` records whose title matches words of `query`, best first,
+    /// with their relevance scores. Scores only compare within one backend.
+    async fn search(&self, query: &str, limit: usize) -> anyhow::Result<Vec<(T, f64)>>;
+
+    /// Writes `items`, whose ids are distinct, all at once: if one fails, none
+    /// are written, unless [`Imported::atomic`] says the backend cannot
+    /// promise that. With `upsert`, records stored under the same id are
+    /// replaced, otherwise the import fails with [`Conflict`].
+    async fn import(&self, items: Vec<T>, upsert: bool) -> anyhow::Result<Imported>;
+}
+
+/// What [`Repo::import`] wrote.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Imported {
+    pub created: u64,
+    pub replaced: u64,
+    /// Whether the records were written in one transaction. MongoDB
+    /// deployments without transactions, i.e. standalone servers, write them
+    /// one by one, so a failure can leave some written.
+    pub atomic: bool,
+}
+
+/// Field a list is ordered by. Ties are broken by id in the same direction.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SortKey {
+    #[default]
+    Id,
+    Title,
+    Created,
+}
+
+impl SortKey {
+    /// Name of the stored field.
+    pub fn field(self) -> &'static str {
+        match self {
+            SortKey::Id => "id",
+            SortKey::Title => "title",
+            SortKey::Created => "created_at",
+        }
+    }
+}
+
+/// Filters, order and window for [`Repo::list`].
+#[derive(Clone, Debug)]
+pub struct ListQuery {
+    pub offset: u64,
+    pub limit: u64,
+    pub sort: SortKey,
+    pub descending: bool,
+    /// Case-insensitive substring of the title.
+    pub title: Option<String>,
+    /// Tags records must all carry, or with `any_tag`, one of which at least.
+    pub tags: Vec<String>,
+    pub any_tag: bool,
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+}
+
+impl Default for ListQuery {
+    fn default() -> Self {
+        Self {
+            offset: 0,
+            limit: 50,
+            sort: SortKey::default(),
+            descending: false,
+            title: None,
+            tags: Vec::new(),
+            any_tag: false,
+            created_after: None,
+            created_before: None,
+        }
+    }
+}
+
+impl ListQuery {
+    /// Whether `item` passes the filters. Backends that cannot filter natively use this.
+    pub fn matches<T: Record>(&self, item: &T) -> bool {
+        let title = self.title.as_ref().map(|title| title.to_lowercase());
+        let created = item.created_at();
+
+        let has_tag = |tag: &String| item.tags().binary_search(tag).is_ok();
+        let tagged = match self.any_tag {
+            true => self.tags.is_empty() || self.tags.iter().any(has_tag),
+            false => self.tags.iter().all(has_tag),
+        };
+
+        title.is_none_or(|title| item.title().to_lowercase().contains(&title))
+            && tagged
+            && self
+                .created_after
+                .is_none_or(|after| created.is_some_and(|at| at > after))
+            && self
+                .created_before
+                .is_none_or(|before| created.is_some_and(|at| at < before))
+    }
+
+    /// Orders `a` and `b` the way the backends' native sorts do.
+    pub fn compare<T: Record>(&self, a: &T, b: &T) -> std::cmp::Ordering {
+        let ordering = match self.sort {
+            SortKey::Id => a.id().cmp(&b.id()),
+            SortKey::Title => a.title().cmp(b.title()).then(a.id().cmp(&b.id())),
+            SortKey::Created => a
+                .created_at()
+                .cmp(&b.created_at())
+                .then(a.id().cmp(&b.id())),
+        };
+        if self.descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    }
+}
+
+/// Formats `at` the way [`Record`] timestamps are stored, for comparing in queries.
+pub fn stored_timestamp(at: DateTime<Utc>) -> String {
+    at.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// Something with the same id or key already exists. Holds what it is.
+#[derive(Debug)]
+pub struct Conflict(pub String);
+
+impl Conflict {
+    pub fn id(id: i32) -> Self {
+        Conflict(format!("A record with id {id}"))
+    }
+
+    pub fn key<V: Keyed>(key: &str) -> Self {
+        Conflict(format!("A {} with {} `{key}`", V::NOUN, V::KEY))
+    }
+}
+
+impl std::fmt::Display for Conflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} already exists", self.0)
+    }
+}
+
+impl std::error::Error for Conflict {}
+
+/// Operations over one collection of [`Keyed`] documents.
+#[async_trait]
+pub trait KeyedRepo<V: Keyed>: Send + Sync {
+    async fn get(&self, key: &str) -> anyhow::Result<Option<V>>;
+
+    /// Fails with [`Conflict`] if the key is taken.
+    async fn insert(&self, value: V) -> anyhow::Result<()>;
+
+    /// Replaces the document with the key of `value`. Returns `false` if there
+    /// is none.
+    async fn replace(&self, value: V) -> anyhow::Result<bool>;
+
+    /// Returns `false` if nothing was stored under `key`.
+    async fn remove(&self, key: &str) -> anyhow::Result<bool>;
+
+    /// Every document, ordered by key.
+    async fn list(&self) -> anyhow::Result<Vec<V>>;
+
+    /// Number of stored documents.
+    async fn count(&self) -> anyhow::Result<u64>;
+}
+
+/// Storage backend shared by every route handler.
+///
+/// Selected at startup by the `database_url` setting, see [`init_store`].
+#[async_trait]
+pub trait DioStore: Send + Sync {
+    /// Checks that the backend answers. Embedded backends always do.
+    async fn ping(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Readies the backend for serving once it answers, e.g. creates indexes.
+    async fn prepare(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Releases connections on shutdown, once no more requests are served.
+    async fn close(&self) {}
+
+    /// Opens the entries of `kind`, kept in `collection`. Tables are created
+    /// right away, indexes by [`Repo::prepare`]. Every call opens a new
+    /// handle, which in memory starts out empty, so [`Kinds`] opens each
+    /// collection once.
+    async fn entries(&self, kind: &Kind, collection: &str) -> anyhow::Result<Arc<dyn Repo<Entry>>>;
+
+    /// Kinds registered through the API, keyed by name.
+    fn kind_defs(&self) -> &dyn KeyedRepo<KindDef>;
+
+    fn users(&self) -> &dyn KeyedRepo<User>;
+
+    /// Login sessions, keyed by the digest of their token.
+    fn sessions(&self) -> &dyn KeyedRepo<Session>;
+
+    /// API keys, keyed by their id.
+    fn api_keys(&self) -> &dyn KeyedRepo<ApiKey>;
+}
+
+/// Builds the backend named by the scheme of `config.database_url`.
+///
+/// * `mongodb://...` or `mongodb+srv://...` connects to MongoDB.
+/// * `sqlite://path/to/dio.db` opens (or creates) an embedded SQLite file.
+/// * `memory://` keeps everything in process.
+pub async fn init_store(config: &Config) -> Result<Arc<dyn DioStore>, DioError> {
+    let url = &config.database_url;
+    match url.split_once("://") {
+        Some(("mongodb" | "mongodb+srv", _)) => Ok(Arc::new(DioDB::init(config).await?)),
+        Some(("sqlite", path)) => match SqliteStore::open(path, &config.collections) {
+            Ok(store) => Ok(Arc::new(store)),
+            Err(e) => Err(DioError::Storage(
+                e.context(format!("Failed to open SQLite database `{path}`")),
+            )),
+        },
+        Some(("memory", _)) => Ok(Arc::new(MemoryStore::default())),
+        _ => Err(DioError::Config(format!(
+            "Unsupported database URL `{url}`. Expected a `mongodb://`, `sqlite://` or `memory://` URL"
+        ))),
+    }
+}
+
+/// First pause between pings in [`connect`], doubled after every failure.
+const RETRY_FIRST: Duration = Duration::from_millis(250);
+/// Longest pause between pings in [`connect`].
+const RETRY_MAX: Duration = Duration::from_secs(5);
+/// Longest wait for one ping in [`connect`].
+const PING_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Pings `store` until it answers, backing off exponentially, then prepares it.
+/// Gives up once `config.connect_timeout` has passed.
+pub async fn connect(store: &dyn DioStore, config: &Config) -> Result<(), DioError> {
+    let deadline = Instant::now() + config.connect_timeout;
+    let mut delay = RETRY_FIRST;
+    for attempt in 1.. {
+        let wait = PING_TIMEOUT.min(deadline.saturating_duration_since(Instant::now()));
+        let err = match time::timeout(wait, store.ping()).await {
+            Ok(Ok(())) => break,
+            Ok(Err(err)) => err,
+            Err(_) => anyhow!("no answer within {wait:.1?}"),
+        };
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(DioError::Storage(err.context(format!(
+                "The database did not answer within {:?}, after {attempt} attempts",
+                config.connect_timeout
+            ))));
+        }
+        let delay_now = delay.min(remaining);
+        tracing::warn!(
+            attempt,
+            error = format!("{err:#}"),
+            "the database did not answer, retrying in {delay_now:.1?}"
+        );
+        time::sleep(delay_now).await;
+        delay = (delay * 2).min(RETRY_MAX);
+    }
+    Ok(store.prepare().await?)
+}
 
-// use futures::stream::TryStreamExt;
 // See https://github.com/actix/examples/tree/master/databases/mongodb
 pub struct DioDB {
-    coll_facts: Collection<Facts>,
-    coll_principles: Collection<Principles>,
+    client: Client,
+    db: mongodb::Database,
+    counters: Collection<Document>,
+    coll_kinds: Collection<KindDef>,
+    coll_users: Collection<User>,
+    coll_sessions: Collection<Session>,
+    coll_api_keys: Collection<ApiKey>,
 }
 
 impl DioDB {
-    pub async fn init() -> Client {
-        dotenv().ok();
-
-        // Load the MongoDB connection string from an environment variable:
-        let client_uri: String = get_env_var("MONGODB_URI").unwrap(); // let client_options = ClientOptions::parse(client_uri).await?; // let client = Client::with_options(client_options)?; // let database = client.database("testDB"); // println!("{:?}", &database);
-
+    /// Sets up the client without connecting, see [`connect`].
+    pub async fn init(config: &Config) -> Result<Self, DioError> {
+        let names = &config.collections;
         // Workaround for a DNS issue on Windows:
-        let options: ClientOptions = match ClientOptions::parse_with_resolver_config(
-            &client_uri,
+        let options: ClientOptions = ClientOptions::parse_with_resolver_config(
+            &config.database_url,
             ResolverConfig::cloudflare(),
         )
         .await
-        {
-            Ok(t) => t,
-            Err(e) => unwrap_failed_options("called `Result::unwrap()` on an `Err` value", &e),
-        };
+        .map_err(|e| {
+            DioError::Config(format!(
+                "Failed to parse the MongoDB connection string: {e}"
+            ))
+        })?;
 
         // A Client is needed to connect to MongoDB:
-        let client: Client = match Client::with_options(options) {
-            Ok(t) => t,
-            Err(e) => unwrap_failed("called `Result::unwrap()` on an `Err` value", &e),
-        };
-        client
+        let client: Client =
+            Client::with_options(options).map_err(|e| mongo_failed("connecting", e))?;
+
+        let db: mongodb::Database = client.database(&config.db_name);
+        Ok(DioDB {
+            counters: db.collection(&names.counters),
+            coll_kinds: db.collection(&names.kinds),
+            coll_users: db.collection(&names.users),
+            coll_sessions: db.collection(&names.sessions),
+            coll_api_keys: db.collection(&names.api_keys),
+            db,
+            client,
+        })
+    }
+}
+
+#[async_trait]
+impl DioStore for DioDB {
+    async fn ping(&self) -> anyhow::Result<()> {
+        self.db.run_command(doc! {"ping": 1}, None).await?;
+        Ok(())
+    }
+
+    /// Waits for cursors and sessions in use to be dropped, then closes the
+    /// connection pools.
+    async fn close(&self) {
+        self.client.clone().shutdown().await;
+    }
+
+    async fn prepare(&self) -> anyhow::Result<()> {
+        (prepare_keyed(&self.coll_kinds).await)
+            .context("MongoDB failed while preparing the kinds collection")?;
+        (prepare_keyed(&self.coll_users).await)
+            .context("MongoDB failed while preparing the users collection")?;
+        (prepare_keyed(&self.coll_sessions).await)
+            .context("MongoDB failed while preparing the sessions collection")?;
+        (prepare_keyed(&self.coll_api_keys).await)
+            .context("MongoDB failed while preparing the API keys collection")?;
+        Ok(())
+    }
 
-        // let db: mongodb::Database = client.database("dio");
-        // println!("Connected to the database: {}", db.name().to_string());
+    async fn entries(&self, kind: &Kind, collection: &str) -> anyhow::Result<Arc<dyn Repo<Entry>>> {
+        Ok(Arc::new(MongoRepo::new(
+            kind.clone(),
+            self.db.collection(collection),
+            self.counters.clone(),
+        )))
+    }
+
+    fn kind_defs(&self) -> &dyn KeyedRepo<KindDef> {
+        &self.coll_kinds
+    }
+
+    fn users(&self) -> &dyn KeyedRepo<User> {
+        &self.coll_users
+    }
 
-        // let coll_facts: Collection<Facts> = db.collection::<Facts>("facts");
-        // let coll_principles: Collection<Principles> = db.collection::<Principles>("principles");
+    fn sessions(&self) -> &dyn KeyedRepo<Session> {
+        &self.coll_sessions
+    }
 
-        // DioDB {
-        //     coll_facts,
-        //     coll_principles,
-        // }
+    fn api_keys(&self) -> &dyn KeyedRepo<ApiKey> {
+        &self.coll_api_keys
     }
 }
 
-fn unwrap_failed_options(arg: &str, e: &mongodb::error::Error) -> ClientOptions {
-    eprintln!(
-        "Failed to connect while parsing the MongoDB URI connection string: {}.\nThe error was: {}",
-        arg, e
-    );
-    std::process::exit(1);
+/// A collection plus its `{ _id: <collection name>, seq: <last id> }` document
+/// in the shared counters collection.
+pub struct MongoRepo<T> {
+    kind: Kind,
+    coll: Collection<T>,
+    counters: Collection<Document>,
 }
 
-fn unwrap_failed(arg: &str, e: &mongodb::error::Error) -> Client {
-    eprintln!("Failed to connect to MongoDB: {}. Error: {}", arg, e);
-    std::process::exit(1);
+impl<T: Record> MongoRepo<T> {
+    fn new(kind: Kind, coll: Collection<T>, counters: Collection<Document>) -> Self {
+        Self {
+            kind,
+            coll,
+            counters,
+        }
+    }
+
+    /// Sets the kind of documents written before entries had one, creates the
+    /// unique index on `id`, the text index on `title` and the index on
+    /// `tags`, and moves the counter past ids that were inserted before
+    /// counters existed.
+    async fn prepare_collection(&self) -> mongodb::error::Result<()> {
+        self.coll
+            .update_many(
+                doc! {"kind": {"$exists": false}},
+                doc! {"$set": {"kind": self.kind.as_str()}},
+                None,
+            )
+            .await?;
+        let unique_id = IndexModel::builder()
+            .keys(doc! {"id": 1})
+            .options(IndexOptions::builder().unique(true).build())
+            .build();
+        self.coll.create_index(unique_id, None).await?;
+        let title_text = IndexModel::builder().keys(doc! {"title": "text"}).build();
+        self.coll.create_index(title_text, None).await?;
+        let tags = IndexModel::builder().keys(doc! {"tags": 1}).build();
+        self.coll.create_index(tags, None).await?;
+
+        let newest = FindOneOptions::builder().sort(doc! {"id": -1}).build();
+        if let Some(item) = self.coll.find_one(None, newest).await? {
+            self.bump_counter(item.id()).await?;
+        }
+        Ok(())
+    }
+
+    async fn bump_counter(&self, id: i32) -> mongodb::error::Result<()> {
+        let upsert = UpdateOptions::builder().upsert(true).build();
+        self.counters
+            .update_one(
+                doc! {"_id": self.coll.name()},
+                doc! {"$max": {"seq": id}},
+                upsert,
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Writes `items` one by one, in the transaction of `session` if given.
+    async fn write_each(
+        &self,
+        items: &[T],
+        upsert: bool,
+        mut session: Option<&mut ClientSession>,
+    ) -> anyhow::Result<Imported> {
+        let mut imported = Imported::default();
+        for item in items {
+            let filter = doc! {"id": item.id()};
+            if upsert {
+                let options = ReplaceOptions::builder().upsert(true).build();
+                let result = match session.as_deref_mut() {
+                    Some(session) => {
+                        (self.coll)
+                            .replace_one_with_session(filter, item, options, session)
+                            .await?
+                    }
+                    None => self.coll.replace_one(filter, item, options).await?,
+                };
+                match result.matched_count {
+                    0 => imported.created += 1,
+                    _ => imported.replaced += 1,
+                }
+                continue;
+            }
+            let result = match session.as_deref_mut() {
+                Some(session) => self.coll.insert_one_with_session(item, None, session).await,
+                None => self.coll.insert_one(item, None).await,
+            };
+            match result {
+                Ok(_) => imported.created += 1,
+                Err(e) if is_duplicate_key(&e) => return Err(Conflict::id(item.id()).into()),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(imported)
+    }
 }
 
-pub async fn print_db_coll_names(client: Client, db: &mongodb::Database) {
-    // Print the databases in our MongoDB cluster:
-    println!("Databases");
-    for name in client.list_database_names(None, None).await.unwrap() {
-        println!("- {}", name);
+#[async_trait]
+impl<T: Record> Repo<T> for MongoRepo<T> {
+    async fn prepare(&self) -> anyhow::Result<()> {
+        (self.prepare_collection().await).with_context(|| {
+            format!(
+                "MongoDB failed while preparing the {} collection",
+                self.coll.name()
+            )
+        })
+    }
+
+    async fn list(&self, query: &ListQuery) -> anyhow::Result<(Vec<T>, u64)> {
+        let filter = filter_doc(query);
+        let options = FindOptions::builder()
+            .sort(sort_doc(query))
+            .skip(query.offset)
+            .limit(i64::try_from(query.limit)?)
+            .build();
+
+        let total = self.coll.count_documents(filter.clone(), None).await?;
+        let items = self.coll.find(filter, options).await?.try_collect().await?;
+        Ok((items, total))
+    }
+
+    async fn stream(
+        &self,
+        query: &ListQuery,
+    ) -> anyhow::Result<BoxStream<'static, anyhow::Result<T>>> {
+        let options = FindOptions::builder().sort(sort_doc(query)).build();
+        let cursor = self.coll.find(filter_doc(query), options).await?;
+        Ok(cursor.map_err(anyhow::Error::from).boxed())
+    }
+
+    async fn get(&self, id: i32) -> anyhow::Result<Option<T>> {
+        Ok(self.coll.find_one(doc! {"id": id}, None).await?)
+    }
+
+    async fn ids(&self) -> anyhow::Result<Vec<i32>> {
+        let mut ids = (self.coll.distinct("id", None, None).await?)
+            .into_iter()
+            .filter_map(|id| id.as_i32())
+            .collect::<Vec<_>>();
+        ids.sort_unstable();
+        Ok(ids)
+    }
+
+    async fn count(&self) -> anyhow::Result<u64> {
+        Ok(self.coll.count_documents(None, None).await?)
     }
-    // Print the collection in our "dio" MongoDB database in cluster:
-    println!("Collections");
-    for collection_name in db.list_collection_names(None).await.unwrap() {
-        println!("- {}", collection_name);
+
+    async fn next_id(&self) -> anyhow::Result<i32> {
+        let options = FindOneAndUpdateOptions::builder()
+            .upsert(true)
+            .return_document(ReturnDocument::After)
+            .build();
+        let counter = self
+            .counters
+            .find_one_and_update(
+                doc! {"_id": self.coll.name()},
+                doc! {"$inc": {"seq": 1}},
+                options,
+            )
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("upserting the counter returned nothing"))?;
+        Ok(counter.get_i32("seq")?)
+    }
+
+    async fn create(&self, item: T) -> anyhow::Result<T> {
+        if let Err(e) = self.coll.insert_one(&item, None).await {
+            return match *e.kind {
+                _ if is_duplicate_key(&e) => Err(Conflict::id(item.id()).into()),
+                _ => Err(e.into()),
+            };
+        }
+        self.bump_counter(item.id()).await?;
+        Ok(item)
+    }
+
+    async fn update(&self, id: i32, item: T) -> anyhow::Result<Option<T>> {
+        let result = self.coll.replace_one(doc! {"id": id}, &item, None).await?;
+        Ok((result.matched_count > 0).then_some(item))
     }
+
+    async fn delete(&self, id: i32) -> anyhow::Result<bool> {
+        let result = self.coll.delete_one(doc! {"id": id}, None).await?;
+        Ok(result.deleted_count > 0)
+    }
+
+    async fn tags(&self) -> anyhow::Result<Vec<TagCount>> {
+        let pipeline = [
+            doc! {"$unwind": "$tags"},
+            doc! {"$group": {"_id": "$tags", "count": {"$sum": 1}}},
+            doc! {"$sort": {"_id": 1}},
+        ];
+        let mut counted = self.coll.aggregate(pipeline, None).await?;
+        let mut tags = Vec::new();
+        while let Some(doc) = counted.try_next().await? {
+            // `$sum` counts in an int32 until it overflows.
+            let count = (doc.get_i32("count").map(i64::from)).or_else(|_| doc.get_i64("count"))?;
+            tags.push(TagCount {
+                tag: doc.get_str("_id")?.to_owned(),
+                count: u64::try_from(count)?,
+            });
+        }
+        Ok(tags)
+    }
+
+    async fn search(&self, query: &str, limit: usize) -> anyhow::Result<Vec<(T, f64)>> {
+        let options = FindOptions::builder()
+            .projection(doc! {"score": {"$meta": "textScore"}})
+            .sort(doc! {"score": {"$meta": "textScore"}})
+            .limit(i64::try_from(limit)?)
+            .build();
+        let mut found = (self.coll.clone_with_type::<Document>())
+            .find(doc! {"$text": {"$search": query}}, options)
+            .await?;
+
+        let mut ranked = Vec::new();
+        while let Some(mut doc) = found.try_next().await? {
+            let score = doc
+                .remove("score")
+                .and_then(|score| score.as_f64())
+                .unwrap_or_default();
+            ranked.push((bson::from_document(doc)?, score));
+        }
+        Ok(ranked)
+    }
+
+    /// Runs in a transaction where the deployment supports them. The counter
+    /// is moved first, outside it: skipping ids is harmless, reusing them is not.
+    async fn import(&self, items: Vec<T>, upsert: bool) -> anyhow::Result<Imported> {
+        if let Some(last) = items.iter().map(Record::id).max() {
+            self.bump_counter(last).await?;
+        }
+        let mut session = self.coll.client().start_session(None).await?;
+        match session.start_transaction(None).await {
+            Ok(()) => {}
+            // Refused up front by standalone servers.
+            Err(e) if matches!(*e.kind, ErrorKind::Transaction { .. }) => {
+                tracing::warn!(
+                    error = %e,
+                    "importing without a transaction, a failure can leave records written"
+                );
+                return self.write_each(&items, upsert, None).await;
+            }
+            Err(e) => return Err(e.into()),
+        }
+        match self.write_each(&items, upsert, Some(&mut session)).await {
+            Ok(imported) => {
+                session.commit_transaction().await?;
+                Ok(Imported {
+                    atomic: true,
+                    ..imported
+                })
+            }
+            Err(e) => {
+                if let Err(abort) = session.abort_transaction().await {
+                    tracing::warn!(error = %abort, "failed to abort the import transaction");
+                }
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Creates the unique index on the key field.
+async fn prepare_keyed<V: Keyed>(coll: &Collection<V>) -> mongodb::error::Result<()> {
+    let unique_key = IndexModel::builder()
+        .keys(doc! {V::KEY: 1})
+        .options(IndexOptions::builder().unique(true).build())
+        .build();
+    coll.create_index(unique_key, None).await?;
+    Ok(())
 }
 
-// let new_doc = doc! { "title": "Parasite", "year": 2020, "plot": "A poor family, the Kims, con their way into becoming the servants of a rich family, the Parks. But their easy life gets complicated when their deception is threatened with exposure.", }; // "released": Utc.with_ymd_and_hms(2020, 2, 7, 0, 0, 0),
-// let insert_facts_result = coll_facts.insert_one(new_doc.clone(), None).await.unwrap();
-// println!(
-// "Inserted with _id: {:?} in collection: {}", insert_facts_result.inserted_id, coll_facts.name(),
-// );
-// let insert_principle_result = coll_principles .insert_one(new_doc.clone(), None) .await .unwrap();
-// println!(
-// "Inserted with _id: {:?} in collection: {}", insert_principle_result.inserted_id, coll_principles.name(),
-// );
+#[async_trait]
+impl<V: Keyed> KeyedRepo<V> for Collection<V> {
+    async fn get(&self, key: &str) -> anyhow::Result<Option<V>> {
+        Ok(self.find_one(doc! {V::KEY: key}, None).await?)
+    }
+
+    async fn insert(&self, value: V) -> anyhow::Result<()> {
+        match self.insert_one(&value, None).await {
+            Ok(_) => Ok(()),
+            Err(e) if is_duplicate_key(&e) => Err(Conflict::key::<V>(value.key()).into()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn replace(&self, value: V) -> anyhow::Result<bool> {
+        let result = (self.replace_one(doc! {V::KEY: value.key()}, &value, None)).await?;
+        Ok(result.matched_count > 0)
+    }
+
+    async fn remove(&self, key: &str) -> anyhow::Result<bool> {
+        let result = self.delete_one(doc! {V::KEY: key}, None).await?;
+        Ok(result.deleted_count > 0)
+    }
+
+    async fn list(&self) -> anyhow::Result<Vec<V>> {
+        let options = FindOptions::builder().sort(doc! {V::KEY: 1}).build();
+        Ok(self.find(None, options).await?.try_collect().await?)
+    }
+
+    async fn count(&self) -> anyhow::Result<u64> {
+        Ok(self.count_documents(None, None).await?)
+    }
+}
+
+/// Whether `e` is a violation of a unique index.
+fn is_duplicate_key(e: &mongodb::error::Error) -> bool {
+    matches!(*e.kind, ErrorKind::Write(WriteFailure::WriteError(ref write)) if write.code == 11000)
+}
+
+/// The filters of `query` as a MongoDB query document.
+fn filter_doc(query: &ListQuery) -> Document {
+    let mut filter = Document::new();
+    if let Some(title) = &query.title {
+        filter.insert(
+            "title",
+            doc! {"$regex": escape_regex(title), "$options": "i"},
+        );
+    }
+    if !query.tags.is_empty() {
+        let operator = if query.any_tag { "$in" } else { "$all" };
+        filter.insert("tags", doc! {operator: &query.tags});
+    }
+    let mut created = Document::new();
+    if let Some(after) = query.created_after {
+        created.insert("$gt", stored_timestamp(after));
+    }
+    if let Some(before) = query.created_before {
+        created.insert("$lt", stored_timestamp(before));
+    }
+    if !created.is_empty() {
+        filter.insert("created_at", created);
+    }
+    filter
+}
+
+/// The order of `query` as a MongoDB sort document, tie-broken by id.
+fn sort_doc(query: &ListQuery) -> Document {
+    let direction = if query.descending { -1 } else { 1 };
+    let mut sort = doc! {query.sort.field(): direction};
+    sort.insert("id", direction);
+    sort
+}
+
+/// Makes `text` match literally inside a `$regex`.
+fn escape_regex(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "\\.+*?()|[]{}^$".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn mongo_failed(doing: &str, e: mongodb::error::Error) -> DioError {
+    DioError::Storage(anyhow::Error::new(e).context(format!("MongoDB failed while {doing}")))
+}
@@ -0,0 +1,152 @@
+//! `kinds` keeps track of the entry kinds the server serves: those of the
+//! `kinds` setting, and those registered through `POST /kinds`, which are
+//! stored so that they survive restarts.
+//!
+//! Each kind has a collection of its own, named after it unless a
+//! `collections.<kind>` setting says otherwise.
+
+use super::{DioStore, Repo};
+use crate::{
+    config::{Collections, Config},
+    error::DioError,
+    model::{Entry, Kind, KindDef},
+};
+use anyhow::Context;
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, RwLock},
+};
+
+/// Names kinds cannot take: the first segments of routes other than
+/// `/{kind}`, which would hide the kind, and the `collections.*` settings of
+/// other collections. A test in `openapi` keeps this in line with the routes.
+pub const RESERVED: [&str; 17] = [
+    "admin",
+    "api_keys",
+    "counters",
+    "docs",
+    "export",
+    "health",
+    "healthcheck",
+    "import",
+    "kinds",
+    "login",
+    "logout",
+    "me",
+    "metrics",
+    "search",
+    "sessions",
+    "today",
+    "users",
+];
+
+/// Checks that `kind` can be added, kept in `collection`, next to the kinds
+/// and other documents kept in the `taken` collections.
+pub fn check(kind: &Kind, collection: &str, taken: &[&str]) -> Result<(), String> {
+    if RESERVED.contains(&kind.as_str()) {
+        return Err(format!("`{kind}` is reserved"));
+    }
+    if taken.contains(&collection) {
+        return Err(format!(
+            "Collection `{collection}` of `{kind}` is already in use"
+        ));
+    }
+    Ok(())
+}
+
+/// The open collection of every kind, shared by the route handlers.
+pub struct Kinds {
+    store: Arc<dyn DioStore>,
+    collections: Collections,
+    configured: Vec<Kind>,
+    repos: RwLock<BTreeMap<Kind, Arc<dyn Repo<Entry>>>>,
+}
+
+impl Kinds {
+    /// Opens the kinds of the `kinds` setting. The registered ones are only
+    /// known once the store answers, see [`Kinds::prepare`].
+    pub async fn open(store: Arc<dyn DioStore>, config: &Config) -> Result<Self, DioError> {
+        let collections = config.collections.clone();
+        let mut repos = BTreeMap::new();
+        for kind in &config.kinds {
+            let repo = store.entries(kind, collections.of(kind)).await?;
+            repos.insert(kind.clone(), repo);
+        }
+        Ok(Self {
+            store,
+            collections,
+            configured: config.kinds.clone(),
+            repos: RwLock::new(repos),
+        })
+    }
+
+    /// Opens the registered kinds, then prepares the collection of every kind.
+    pub async fn prepare(&self) -> anyhow::Result<()> {
+        for def in self.store.kind_defs().list().await? {
+            if self.get(def.kind.as_str()).is_none() {
+                self.open_kind(&def.kind).await?;
+            }
+        }
+        for (kind, repo) in self.all() {
+            (repo.prepare().await).with_context(|| format!("Failed to prepare the {kind}"))?;
+        }
+        Ok(())
+    }
+
+    /// The entries of `kind`, if the server knows it.
+    pub fn get(&self, kind: &str) -> Option<Arc<dyn Repo<Entry>>> {
+        self.repos.read().unwrap().get(kind).cloned()
+    }
+
+    /// Every kind with its entries, by name.
+    pub fn all(&self) -> Vec<(Kind, Arc<dyn Repo<Entry>>)> {
+        let repos = self.repos.read().unwrap();
+        (repos.iter())
+            .map(|(kind, repo)| (kind.clone(), repo.clone()))
+            .collect()
+    }
+
+    /// Whether `kind` comes from the `kinds` setting rather than the API.
+    pub fn is_configured(&self, kind: &Kind) -> bool {
+        self.configured.contains(kind)
+    }
+
+    /// Stores `def` and opens its kind, ready for serving.
+    pub async fn register(&self, def: KindDef) -> Result<Arc<dyn Repo<Entry>>, DioError> {
+        let kind = def.kind.clone();
+        if self.get(kind.as_str()).is_some() {
+            return Err(DioError::Conflict(format!(
+                "The kind `{kind}` already exists"
+            )));
+        }
+        let collection = self.collections.of(&kind);
+        let mut taken = Vec::from(self.collections.reserved());
+        let known = self.all();
+        taken.extend(known.iter().map(|(kind, _)| self.collections.of(kind)));
+        check(&kind, collection, &taken).map_err(DioError::Conflict)?;
+
+        self.store.kind_defs().insert(def).await?;
+        let opened = async {
+            let repo = self.open_kind(&kind).await?;
+            repo.prepare().await?;
+            anyhow::Ok(repo)
+        };
+        match opened.await {
+            Ok(repo) => Ok(repo),
+            Err(e) => {
+                // Left registered, the kind would fail to open on every start.
+                self.repos.write().unwrap().remove(&kind);
+                self.store.kind_defs().remove(kind.as_str()).await?;
+                Err(e.into())
+            }
+        }
+    }
+
+    async fn open_kind(&self, kind: &Kind) -> anyhow::Result<Arc<dyn Repo<Entry>>> {
+        let repo = (self.store)
+            .entries(kind, self.collections.of(kind))
+            .await?;
+        let mut repos = self.repos.write().unwrap();
+        Ok(repos.entry(kind.clone()).or_insert(repo).clone())
+    }
+}
@@ -0,0 +1,297 @@
+//! `memory` is a process-local [`DioStore`] for running dio-server without MongoDB.
+//!
+//! Everything is lost when the server stops.
+
+use super::{Conflict, DioStore, Imported, KeyedRepo, ListQuery, Repo};
+use crate::{
+    model::{ApiKey, Entry, Keyed, Kind, KindDef, Record, Session, TagCount, User},
+    search::Index,
+};
+use async_trait::async_trait;
+use futures::stream::{self, BoxStream, StreamExt};
+use std::{
+    collections::{btree_map, BTreeMap},
+    sync::{
+        atomic::{AtomicI32, Ordering},
+        Arc, RwLock,
+    },
+};
+
+#[derive(Default)]
+pub struct MemoryStore {
+    kinds: MemoryKeyedRepo<KindDef>,
+    users: MemoryKeyedRepo<User>,
+    sessions: MemoryKeyedRepo<Session>,
+    api_keys: MemoryKeyedRepo<ApiKey>,
+}
+
+#[async_trait]
+impl DioStore for MemoryStore {
+    async fn entries(
+        &self,
+        _kind: &Kind,
+        _collection: &str,
+    ) -> anyhow::Result<Arc<dyn Repo<Entry>>> {
+        Ok(Arc::new(MemoryRepo::<Entry>::default()))
+    }
+
+    fn kind_defs(&self) -> &dyn KeyedRepo<KindDef> {
+        &self.kinds
+    }
+
+    fn users(&self) -> &dyn KeyedRepo<User> {
+        &self.users
+    }
+
+    fn sessions(&self) -> &dyn KeyedRepo<Session> {
+        &self.sessions
+    }
+
+    fn api_keys(&self) -> &dyn KeyedRepo<ApiKey> {
+        &self.api_keys
+    }
+}
+
+/// One collection, ordered by id.
+pub struct MemoryRepo<T> {
+    items: RwLock<BTreeMap<i32, T>>,
+    /// Titles of `items`. Only written while holding the `items` write lock.
+    index: RwLock<Index>,
+    /// Last id handed out or created.
+    seq: AtomicI32,
+}
+
+impl<T> Default for MemoryRepo<T> {
+    fn default() -> Self {
+        Self {
+            items: RwLock::new(BTreeMap::new()),
+            index: RwLock::new(Index::default()),
+            seq: AtomicI32::new(0),
+        }
+    }
+}
+
+impl<T: Record> MemoryRepo<T> {
+    /// Every record passing the filters of `query`, in its order.
+    fn matching(&self, query: &ListQuery) -> Vec<T> {
+        let mut items: Vec<T> = (self.items.read().unwrap().values())
+            .filter(|item| query.matches(*item))
+            .cloned()
+            .collect();
+        items.sort_by(|a, b| query.compare(a, b));
+        items
+    }
+}
+
+#[async_trait]
+impl<T: Record> Repo<T> for MemoryRepo<T> {
+    async fn list(&self, query: &ListQuery) -> anyhow::Result<(Vec<T>, u64)> {
+        let items = self.matching(query);
+        let total = items.len() as u64;
+        let window = items
+            .into_iter()
+            .skip(usize::try_from(query.offset)?)
+            .take(usize::try_from(query.limit)?)
+            .collect();
+        Ok((window, total))
+    }
+
+    /// Streams a snapshot taken when the call is made.
+    async fn stream(
+        &self,
+        query: &ListQuery,
+    ) -> anyhow::Result<BoxStream<'static, anyhow::Result<T>>> {
+        Ok(stream::iter(self.matching(query).into_iter().map(Ok)).boxed())
+    }
+
+    async fn get(&self, id: i32) -> anyhow::Result<Option<T>> {
+        Ok(self.items.read().unwrap().get(&id).cloned())
+    }
+
+    async fn ids(&self) -> anyhow::Result<Vec<i32>> {
+        Ok(self.items.read().unwrap().keys().copied().collect())
+    }
+
+    async fn count(&self) -> anyhow::Result<u64> {
+        Ok(self.items.read().unwrap().len() as u64)
+    }
+
+    async fn next_id(&self) -> anyhow::Result<i32> {
+        Ok(self.seq.fetch_add(1, Ordering::SeqCst) + 1)
+    }
+
+    async fn create(&self, item: T) -> anyhow::Result<T> {
+        match self.items.write().unwrap().entry(item.id()) {
+            btree_map::Entry::Occupied(_) => Err(Conflict::id(item.id()).into()),
+            btree_map::Entry::Vacant(slot) => {
+                self.seq.fetch_max(item.id(), Ordering::SeqCst);
+                self.index.write().unwrap().insert(item.id(), item.title());
+                Ok(slot.insert(item).clone())
+            }
+        }
+    }
+
+    async fn update(&self, id: i32, item: T) -> anyhow::Result<Option<T>> {
+        let mut items = self.items.write().unwrap();
+        match items.get_mut(&id) {
+            Some(slot) => {
+                self.index.write().unwrap().insert(id, item.title());
+                *slot = item.clone();
+                Ok(Some(item))
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn delete(&self, id: i32) -> anyhow::Result<bool> {
+        let mut items = self.items.write().unwrap();
+        self.index.write().unwrap().remove(id);
+        Ok(items.remove(&id).is_some())
+    }
+
+    async fn tags(&self) -> anyhow::Result<Vec<TagCount>> {
+        let mut counts = BTreeMap::<String, u64>::new();
+        for item in self.items.read().unwrap().values() {
+            for tag in item.tags() {
+                *counts.entry(tag.clone()).or_default() += 1;
+            }
+        }
+        Ok((counts.into_iter())
+            .map(|(tag, count)| TagCount { tag, count })
+            .collect())
+    }
+
+    async fn search(&self, query: &str, limit: usize) -> anyhow::Result<Vec<(T, f64)>> {
+        let items = self.items.read().unwrap();
+        let ranked = self.index.read().unwrap().search(query, limit);
+        Ok(ranked
+            .into_iter()
+            .filter_map(|(id, score)| Some((items.get(&id)?.clone(), score)))
+            .collect())
+    }
+
+    async fn import(&self, items: Vec<T>, upsert: bool) -> anyhow::Result<Imported> {
+        let mut stored = self.items.write().unwrap();
+        if !upsert {
+            if let Some(taken) = items.iter().find(|item| stored.contains_key(&item.id())) {
+                return Err(Conflict::id(taken.id()).into());
+            }
+        }
+        let mut index = self.index.write().unwrap();
+        let mut imported = Imported {
+            atomic: true,
+            ..Imported::default()
+        };
+        for item in items {
+            self.seq.fetch_max(item.id(), Ordering::SeqCst);
+            index.insert(item.id(), item.title());
+            match stored.insert(item.id(), item) {
+                Some(_) => imported.replaced += 1,
+                None => imported.created += 1,
+            }
+        }
+        Ok(imported)
+    }
+}
+
+pub struct MemoryKeyedRepo<V> {
+    items: RwLock<BTreeMap<String, V>>,
+}
+
+impl<V> Default for MemoryKeyedRepo<V> {
+    fn default() -> Self {
+        Self {
+            items: RwLock::new(BTreeMap::new()),
+        }
+    }
+}
+
+#[async_trait]
+impl<V: Keyed> KeyedRepo<V> for MemoryKeyedRepo<V> {
+    async fn get(&self, key: &str) -> anyhow::Result<Option<V>> {
+        Ok(self.items.read().unwrap().get(key).cloned())
+    }
+
+    async fn insert(&self, value: V) -> anyhow::Result<()> {
+        match self.items.write().unwrap().entry(value.key().to_owned()) {
+            btree_map::Entry::Occupied(_) => Err(Conflict::key::<V>(value.key()).into()),
+            btree_map::Entry::Vacant(slot) => {
+                slot.insert(value);
+                Ok(())
+            }
+        }
+    }
+
+    async fn replace(&self, value: V) -> anyhow::Result<bool> {
+        match self.items.write().unwrap().get_mut(value.key()) {
+            Some(slot) => {
+                *slot = value;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn remove(&self, key: &str) -> anyhow::Result<bool> {
+        Ok(self.items.write().unwrap().remove(key).is_some())
+    }
+
+    async fn list(&self) -> anyhow::Result<Vec<V>> {
+        Ok(self.items.read().unwrap().values().cloned().collect())
+    }
+
+    async fn count(&self) -> anyhow::Result<u64> {
+        Ok(self.items.read().unwrap().len() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db::SortKey, model::Kind};
+
+    fn fact(id: i32, title: &str) -> Entry {
+        Entry::new(Kind::new("facts").unwrap(), id, title).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn keeps_records_by_id() {
+        let repo = MemoryRepo::<Entry>::default();
+        for (id, title) in [(2, "Dogs bark"), (1, "Cats purr")] {
+            repo.create(fact(id, title)).await.unwrap();
+        }
+        let all = ListQuery::default();
+        let duplicate = repo.create(fact(1, "Birds sing")).await.unwrap_err();
+        assert!(duplicate.is::<Conflict>());
+        assert_eq!(repo.next_id().await.unwrap(), 3);
+        assert_eq!(
+            repo.list(&all).await.unwrap().0,
+            [fact(1, "Cats purr"), fact(2, "Dogs bark")]
+        );
+
+        let query = ListQuery {
+            offset: 1,
+            limit: 1,
+            sort: SortKey::Title,
+            descending: true,
+            ..ListQuery::default()
+        };
+        let page = (vec![fact(1, "Cats purr")], 2);
+        assert_eq!(repo.list(&query).await.unwrap(), page);
+        let query = ListQuery {
+            title: Some("DOG".to_owned()),
+            ..ListQuery::default()
+        };
+        let page = (vec![fact(2, "Dogs bark")], 1);
+        assert_eq!(repo.list(&query).await.unwrap(), page);
+        assert_eq!(repo.get(2).await.unwrap(), Some(fact(2, "Dogs bark")));
+
+        let updated = repo.update(2, fact(2, "Dogs nap")).await.unwrap();
+        assert_eq!(updated, Some(fact(2, "Dogs nap")));
+        assert_eq!(repo.update(3, fact(3, "Birds sing")).await.unwrap(), None);
+
+        assert!(repo.delete(1).await.unwrap());
+        assert!(!repo.delete(1).await.unwrap());
+        assert_eq!(repo.list(&all).await.unwrap().0, [fact(2, "Dogs nap")]);
+    }
+}
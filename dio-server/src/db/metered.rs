@@ -0,0 +1,270 @@
+//! `metered` wraps another [`DioStore`] and times every operation into
+//! [`Metrics`], labelled by collection and operation. Each operation also runs
+//! in a `db` span, a child of the request it serves.
+
+use super::{Conflict, DioStore, Imported, KeyedRepo, ListQuery, Repo};
+use crate::{
+    metrics::Metrics,
+    model::{ApiKey, Entry, Keyed, Kind, KindDef, Record, Session, TagCount, User},
+};
+use async_trait::async_trait;
+use futures::{stream::BoxStream, Future};
+use std::{sync::Arc, time::Instant};
+use tracing::Instrument;
+
+pub struct MeteredStore {
+    meter: Meter,
+    kinds: MeteredKeyedRepo<KindDef>,
+    users: MeteredKeyedRepo<User>,
+    sessions: MeteredKeyedRepo<Session>,
+    api_keys: MeteredKeyedRepo<ApiKey>,
+}
+
+impl MeteredStore {
+    pub fn new(inner: Arc<dyn DioStore>, metrics: Arc<Metrics>) -> Self {
+        let meter = Meter { inner, metrics };
+        Self {
+            kinds: MeteredKeyedRepo::new(meter.clone(), "kinds", |store| store.kind_defs()),
+            users: MeteredKeyedRepo::new(meter.clone(), "users", |store| store.users()),
+            sessions: MeteredKeyedRepo::new(meter.clone(), "sessions", |store| store.sessions()),
+            api_keys: MeteredKeyedRepo::new(meter.clone(), "api_keys", |store| store.api_keys()),
+            meter,
+        }
+    }
+}
+
+#[async_trait]
+impl DioStore for MeteredStore {
+    async fn ping(&self) -> anyhow::Result<()> {
+        self.meter.inner.ping().await
+    }
+
+    async fn prepare(&self) -> anyhow::Result<()> {
+        self.meter.inner.prepare().await
+    }
+
+    async fn close(&self) {
+        self.meter.inner.close().await
+    }
+
+    /// Labels operations with `kind`, which is stable across collection renames.
+    async fn entries(&self, kind: &Kind, collection: &str) -> anyhow::Result<Arc<dyn Repo<Entry>>> {
+        let repo = self.meter.inner.entries(kind, collection).await?;
+        Ok(Arc::new(MeteredRepo {
+            meter: self.meter.clone(),
+            collection: kind.to_string(),
+            repo,
+        }))
+    }
+
+    fn kind_defs(&self) -> &dyn KeyedRepo<KindDef> {
+        &self.kinds
+    }
+
+    fn users(&self) -> &dyn KeyedRepo<User> {
+        &self.users
+    }
+
+    fn sessions(&self) -> &dyn KeyedRepo<Session> {
+        &self.sessions
+    }
+
+    fn api_keys(&self) -> &dyn KeyedRepo<ApiKey> {
+        &self.api_keys
+    }
+}
+
+#[derive(Clone)]
+struct Meter {
+    inner: Arc<dyn DioStore>,
+    metrics: Arc<Metrics>,
+}
+
+impl Meter {
+    /// Awaits `operation` on `collection`, recording how long it took. A
+    /// [`Conflict`] is an answer rather than a failure.
+    async fn time<R>(
+        &self,
+        collection: &str,
+        operation: &str,
+        future: impl Future<Output = anyhow::Result<R>>,
+    ) -> anyhow::Result<R> {
+        let span = tracing::debug_span!("db", collection, operation);
+        async {
+            let started = Instant::now();
+            let result = future.await;
+            let failed = result.as_ref().is_err_and(|e| !e.is::<Conflict>());
+            let seconds = started.elapsed().as_secs_f64();
+            (self.metrics).observe_db(collection, operation, seconds, failed);
+            let latency_ms = (seconds * 1e6).round() / 1e3;
+            tracing::debug!(latency_ms, failed, "db operation done");
+            result
+        }
+        .instrument(span)
+        .await
+    }
+}
+
+pub struct MeteredRepo<T> {
+    meter: Meter,
+    collection: String,
+    repo: Arc<dyn Repo<T>>,
+}
+
+impl<T: Record> MeteredRepo<T> {
+    fn repo(&self) -> &dyn Repo<T> {
+        self.repo.as_ref()
+    }
+}
+
+#[async_trait]
+impl<T: Record> Repo<T> for MeteredRepo<T> {
+    async fn prepare(&self) -> anyhow::Result<()> {
+        (self.meter)
+            .time(&self.collection, "prepare", self.repo().prepare())
+            .await
+    }
+
+    async fn list(&self, query: &ListQuery) -> anyhow::Result<(Vec<T>, u64)> {
+        (self.meter)
+            .time(&self.collection, "list", self.repo().list(query))
+            .await
+    }
+
+    /// Times opening the stream only; its items arrive as the client reads them.
+    async fn stream(
+        &self,
+        query: &ListQuery,
+    ) -> anyhow::Result<BoxStream<'static, anyhow::Result<T>>> {
+        (self.meter)
+            .time(&self.collection, "stream", self.repo().stream(query))
+            .await
+    }
+
+    async fn get(&self, id: i32) -> anyhow::Result<Option<T>> {
+        (self.meter)
+            .time(&self.collection, "get", self.repo().get(id))
+            .await
+    }
+
+    async fn ids(&self) -> anyhow::Result<Vec<i32>> {
+        (self.meter)
+            .time(&self.collection, "ids", self.repo().ids())
+            .await
+    }
+
+    async fn count(&self) -> anyhow::Result<u64> {
+        (self.meter)
+            .time(&self.collection, "count", self.repo().count())
+            .await
+    }
+
+    async fn next_id(&self) -> anyhow::Result<i32> {
+        (self.meter)
+            .time(&self.collection, "next_id", self.repo().next_id())
+            .await
+    }
+
+    async fn create(&self, item: T) -> anyhow::Result<T> {
+        (self.meter)
+            .time(&self.collection, "create", self.repo().create(item))
+            .await
+    }
+
+    async fn update(&self, id: i32, item: T) -> anyhow::Result<Option<T>> {
+        (self.meter)
+            .time(&self.collection, "update", self.repo().update(id, item))
+            .await
+    }
+
+    async fn delete(&self, id: i32) -> anyhow::Result<bool> {
+        (self.meter)
+            .time(&self.collection, "delete", self.repo().delete(id))
+            .await
+    }
+
+    async fn tags(&self) -> anyhow::Result<Vec<TagCount>> {
+        (self.meter)
+            .time(&self.collection, "tags", self.repo().tags())
+            .await
+    }
+
+    async fn search(&self, query: &str, limit: usize) -> anyhow::Result<Vec<(T, f64)>> {
+        (self.meter)
+            .time(&self.collection, "search", self.repo().search(query, limit))
+            .await
+    }
+
+    async fn import(&self, items: Vec<T>, upsert: bool) -> anyhow::Result<Imported> {
+        (self.meter)
+            .time(
+                &self.collection,
+                "import",
+                self.repo().import(items, upsert),
+            )
+            .await
+    }
+}
+
+pub struct MeteredKeyedRepo<V> {
+    meter: Meter,
+    collection: &'static str,
+    pick: fn(&dyn DioStore) -> &dyn KeyedRepo<V>,
+}
+
+impl<V: Keyed> MeteredKeyedRepo<V> {
+    fn new(
+        meter: Meter,
+        collection: &'static str,
+        pick: fn(&dyn DioStore) -> &dyn KeyedRepo<V>,
+    ) -> Self {
+        Self {
+            meter,
+            collection,
+            pick,
+        }
+    }
+
+    fn repo(&self) -> &dyn KeyedRepo<V> {
+        (self.pick)(self.meter.inner.as_ref())
+    }
+}
+
+#[async_trait]
+impl<V: Keyed> KeyedRepo<V> for MeteredKeyedRepo<V> {
+    async fn get(&self, key: &str) -> anyhow::Result<Option<V>> {
+        (self.meter)
+            .time(self.collection, "get", self.repo().get(key))
+            .await
+    }
+
+    async fn insert(&self, value: V) -> anyhow::Result<()> {
+        (self.meter)
+            .time(self.collection, "insert", self.repo().insert(value))
+            .await
+    }
+
+    async fn replace(&self, value: V) -> anyhow::Result<bool> {
+        (self.meter)
+            .time(self.collection, "replace", self.repo().replace(value))
+            .await
+    }
+
+    async fn remove(&self, key: &str) -> anyhow::Result<bool> {
+        (self.meter)
+            .time(self.collection, "remove", self.repo().remove(key))
+            .await
+    }
+
+    async fn list(&self) -> anyhow::Result<Vec<V>> {
+        (self.meter)
+            .time(self.collection, "list", self.repo().list())
+            .await
+    }
+
+    async fn count(&self) -> anyhow::Result<u64> {
+        (self.meter)
+            .time(self.collection, "count", self.repo().count())
+            .await
+    }
+}
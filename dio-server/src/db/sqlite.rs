@@ -0,0 +1,758 @@
+//! `sqlite` is an embedded [`DioStore`] for hosts where running `mongod` is overkill.
+//!
+//! Each collection is a table of `(id, doc)` rows where `doc` is the record
+//! serialized as JSON, mirroring how MongoDB stores it. Tables are created
+//! when first opened, along with a counters table holding the last id handed
+//! out per table. Table names come from [`Collections`].
+//!
+//! The tags of entries are also kept in a `"<table>.tags"` table of
+//! `(tag, id)` rows, maintained by triggers, which tag filters look up.
+
+use super::{stored_timestamp, Conflict, DioStore, Imported, KeyedRepo, ListQuery, Repo, SortKey};
+use crate::{
+    config::Collections,
+    model::{ApiKey, Entry, Keyed, Kind, KindDef, Record, Session, TagCount, User},
+    search::Index,
+};
+use actix_web::web;
+use async_trait::async_trait;
+use futures::stream::{self, BoxStream, StreamExt, TryStreamExt};
+use rusqlite::{params, types::Value as SqlValue, Connection, OptionalExtension};
+use std::{
+    marker::PhantomData,
+    sync::{Arc, Mutex, RwLock},
+};
+
+pub struct SqliteStore {
+    conn: Arc<Mutex<Connection>>,
+    /// The counters table.
+    counters: Arc<str>,
+    kinds: SqliteKeyedRepo<KindDef>,
+    users: SqliteKeyedRepo<User>,
+    sessions: SqliteKeyedRepo<Session>,
+    api_keys: SqliteKeyedRepo<ApiKey>,
+}
+
+impl SqliteStore {
+    /// Opens (or creates) the database file at `path`. `:memory:` is accepted too.
+    pub fn open(path: &str, names: &Collections) -> anyhow::Result<Self> {
+        let counters: Arc<str> = names.counters.as_str().into();
+        let conn = Connection::open(path)?;
+        conn.execute(
+            &format!(
+                "CREATE TABLE IF NOT EXISTS \"{counters}\" (name TEXT PRIMARY KEY, seq INTEGER NOT NULL)"
+            ),
+            [],
+        )?;
+        for table in [&names.users, &names.sessions, &names.api_keys, &names.kinds] {
+            conn.execute(
+                &format!(
+                    "CREATE TABLE IF NOT EXISTS \"{table}\" (key TEXT PRIMARY KEY, doc TEXT NOT NULL)"
+                ),
+                [],
+            )?;
+        }
+        let conn = Arc::new(Mutex::new(conn));
+
+        Ok(Self {
+            kinds: SqliteKeyedRepo::new(conn.clone(), &names.kinds),
+            counters,
+            conn: conn.clone(),
+            users: SqliteKeyedRepo::new(conn.clone(), &names.users),
+            sessions: SqliteKeyedRepo::new(conn.clone(), &names.sessions),
+            api_keys: SqliteKeyedRepo::new(conn, &names.api_keys),
+        })
+    }
+}
+
+#[async_trait]
+impl DioStore for SqliteStore {
+    async fn ping(&self) -> anyhow::Result<()> {
+        run(&self.conn, &self.counters, |conn, _| {
+            Ok(conn.query_row("SELECT 1", [], |_| Ok(()))?)
+        })
+        .await
+    }
+
+    /// Creates the table of `kind` if needed, and builds its search index.
+    async fn entries(&self, kind: &Kind, collection: &str) -> anyhow::Result<Arc<dyn Repo<Entry>>> {
+        let (conn, counters) = (self.conn.clone(), self.counters.clone());
+        let (kind, table) = (kind.clone(), collection.to_owned());
+        web::block(move || {
+            create_entries_table(&conn.lock().unwrap(), &kind, &table, &counters)?;
+            let repo = SqliteRepo::<Entry>::open(conn, &table, counters)?;
+            Ok(Arc::new(repo) as Arc<dyn Repo<Entry>>)
+        })
+        .await?
+    }
+
+    fn kind_defs(&self) -> &dyn KeyedRepo<KindDef> {
+        &self.kinds
+    }
+
+    fn users(&self) -> &dyn KeyedRepo<User> {
+        &self.users
+    }
+
+    fn sessions(&self) -> &dyn KeyedRepo<Session> {
+        &self.sessions
+    }
+
+    fn api_keys(&self) -> &dyn KeyedRepo<ApiKey> {
+        &self.api_keys
+    }
+}
+
+fn create_entries_table(
+    conn: &Connection,
+    kind: &Kind,
+    table: &str,
+    counters: &str,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS \"{table}\" (id INTEGER PRIMARY KEY, doc TEXT NOT NULL)"
+        ),
+        [],
+    )?;
+    let tags = format!("\"{table}.tags\"");
+    conn.execute_batch(&format!(
+        "CREATE TABLE IF NOT EXISTS {tags} (tag TEXT NOT NULL, id INTEGER NOT NULL, PRIMARY KEY (tag, id)) WITHOUT ROWID;
+         CREATE INDEX IF NOT EXISTS \"{table}.tags_id\" ON {tags} (id);
+         CREATE TRIGGER IF NOT EXISTS \"{table}.tags_insert\" AFTER INSERT ON \"{table}\" BEGIN
+             INSERT OR IGNORE INTO {tags} SELECT value, new.id FROM json_each(new.doc, '$.tags');
+         END;
+         CREATE TRIGGER IF NOT EXISTS \"{table}.tags_update\" AFTER UPDATE ON \"{table}\" BEGIN
+             DELETE FROM {tags} WHERE id = old.id;
+             INSERT OR IGNORE INTO {tags} SELECT value, new.id FROM json_each(new.doc, '$.tags');
+         END;
+         CREATE TRIGGER IF NOT EXISTS \"{table}.tags_delete\" AFTER DELETE ON \"{table}\" BEGIN
+             DELETE FROM {tags} WHERE id = old.id;
+         END;
+         INSERT OR IGNORE INTO {tags} SELECT t.value, \"{table}\".id FROM \"{table}\", json_each(\"{table}\".doc, '$.tags') t;"
+    ))?;
+    conn.execute(
+        &format!(
+            "INSERT INTO \"{counters}\" (name, seq) SELECT ?1, COALESCE(MAX(id), 0) FROM \"{table}\" WHERE true
+             ON CONFLICT (name) DO UPDATE SET seq = MAX(seq, excluded.seq)"
+        ),
+        [table],
+    )?;
+    // Documents written before entries had a kind.
+    conn.execute(
+        &format!(
+            "UPDATE \"{table}\" SET doc = json_set(doc, '$.kind', ?1) WHERE json_extract(doc, '$.kind') IS NULL"
+        ),
+        [kind.as_str()],
+    )?;
+    Ok(())
+}
+
+/// `WHERE` clause of [`Repo::list`] on `table`, bound to the title, the bounds
+/// from [`timestamp_bounds`] and the tags from [`tag_filter`].
+fn filter(table: &str) -> String {
+    format!(
+        "(?1 IS NULL OR instr(lower(json_extract(doc, '$.title')), lower(?1)) > 0)
+        AND (?2 IS NULL OR json_extract(doc, '$.created_at') > ?2)
+        AND (?3 IS NULL OR json_extract(doc, '$.created_at') < ?3)
+        AND (?4 IS NULL OR id IN (
+            SELECT id FROM \"{table}.tags\" WHERE tag IN (SELECT value FROM json_each(?4))
+            GROUP BY id HAVING COUNT(*) >= ?5))"
+    )
+}
+
+/// Rows fetched per round trip by [`Repo::stream`].
+const STREAM_BATCH: u64 = 256;
+
+fn timestamp_bounds(query: &ListQuery) -> (Option<String>, Option<String>) {
+    (
+        query.created_after.map(stored_timestamp),
+        query.created_before.map(stored_timestamp),
+    )
+}
+
+/// The tags of `query` as a JSON array, if any, and how many of them a record
+/// must carry.
+fn tag_filter(query: &ListQuery) -> anyhow::Result<(Option<String>, usize)> {
+    if query.tags.is_empty() {
+        return Ok((None, 0));
+    }
+    let needed = if query.any_tag { 1 } else { query.tags.len() };
+    Ok((Some(serde_json::to_string(&query.tags)?), needed))
+}
+
+/// Where a batch of [`Repo::stream`] ended: the sort value and id of its last row.
+type Keyset = (SqlValue, i32);
+
+/// The window of `query` from `table`, without counting the total. With
+/// `after`, only rows ordered after that position are selected.
+fn select<T: Record>(
+    conn: &Connection,
+    table: &str,
+    query: &ListQuery,
+    after: Option<&Keyset>,
+) -> anyhow::Result<Vec<(T, Keyset)>> {
+    let (created_after, created_before) = timestamp_bounds(query);
+    let (tags, needed) = tag_filter(query)?;
+    let (direction, past) = if query.descending {
+        ("DESC", "<")
+    } else {
+        ("ASC", ">")
+    };
+    // Missing values sort as '' so that row values never compare to NULL.
+    let sort = match query.sort {
+        SortKey::Id => "id".to_owned(),
+        key => format!("COALESCE(json_extract(doc, '$.{}'), '')", key.field()),
+    };
+    let (last_key, last_id) = match after {
+        Some((key, id)) => (key.clone(), Some(*id)),
+        None => (SqlValue::Null, None),
+    };
+    let mut stmt = conn.prepare(&format!(
+        "SELECT doc, {sort}, id FROM \"{table}\"
+         WHERE {} AND (?7 IS NULL OR ({sort}, id) {past} (?6, ?7))
+         ORDER BY {sort} {direction}, id {direction} LIMIT {} OFFSET {}",
+        filter(table),
+        query.limit,
+        query.offset
+    ))?;
+    let args = params![
+        query.title,
+        created_after,
+        created_before,
+        tags,
+        needed,
+        last_key,
+        last_id
+    ];
+    let rows = stmt.query_map(args, |row| {
+        Ok((row.get::<_, String>(0)?, (row.get(1)?, row.get(2)?)))
+    })?;
+    rows.map(|row| {
+        let (doc, keyset) = row?;
+        Ok((serde_json::from_str(&doc)?, keyset))
+    })
+    .collect()
+}
+
+/// One table. All tables share a single connection.
+pub struct SqliteRepo<T> {
+    conn: Arc<Mutex<Connection>>,
+    table: Arc<str>,
+    /// The counters table.
+    counters: Arc<str>,
+    /// Titles of the table, built on open. Only written while holding `conn`.
+    index: Arc<RwLock<Index>>,
+    _record: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for SqliteRepo<T> {
+    fn clone(&self) -> Self {
+        Self {
+            conn: self.conn.clone(),
+            table: self.table.clone(),
+            counters: self.counters.clone(),
+            index: self.index.clone(),
+            _record: PhantomData,
+        }
+    }
+}
+
+impl<T: Record> SqliteRepo<T> {
+    fn open(conn: Arc<Mutex<Connection>>, table: &str, counters: Arc<str>) -> anyhow::Result<Self> {
+        let mut index = Index::default();
+        {
+            let conn = conn.lock().unwrap();
+            let mut stmt = conn.prepare(&format!("SELECT doc FROM \"{table}\""))?;
+            for doc in stmt.query_map([], |row| row.get::<_, String>(0))? {
+                let item: T = serde_json::from_str(&doc?)?;
+                index.insert(item.id(), item.title());
+            }
+        }
+
+        Ok(Self {
+            conn,
+            table: table.into(),
+            counters,
+            index: Arc::new(RwLock::new(index)),
+            _record: PhantomData,
+        })
+    }
+}
+
+impl<T> SqliteRepo<T> {
+    async fn call<R, F>(&self, f: F) -> anyhow::Result<R>
+    where
+        R: Send + 'static,
+        F: FnOnce(&Connection, &str) -> anyhow::Result<R> + Send + 'static,
+    {
+        run(&self.conn, &self.table, f).await
+    }
+}
+
+/// Runs `f` on actix's blocking thread pool, so workers never wait on disk.
+async fn run<R, F>(conn: &Arc<Mutex<Connection>>, table: &Arc<str>, f: F) -> anyhow::Result<R>
+where
+    R: Send + 'static,
+    F: FnOnce(&Connection, &str) -> anyhow::Result<R> + Send + 'static,
+{
+    let (conn, table) = (conn.clone(), table.clone());
+    web::block(move || f(&conn.lock().unwrap(), &table)).await?
+}
+
+#[async_trait]
+impl<T: Record> Repo<T> for SqliteRepo<T> {
+    async fn list(&self, query: &ListQuery) -> anyhow::Result<(Vec<T>, u64)> {
+        let query = query.clone();
+        self.call(move |conn, table| {
+            let (after, before) = timestamp_bounds(&query);
+            let (tags, needed) = tag_filter(&query)?;
+            let total: u64 = conn.query_row(
+                &format!("SELECT COUNT(*) FROM \"{table}\" WHERE {}", filter(table)),
+                params![query.title, after, before, tags, needed],
+                |row| row.get(0),
+            )?;
+            let items = select(conn, table, &query, None)?;
+            Ok((items.into_iter().map(|(item, _)| item).collect(), total))
+        })
+        .await
+    }
+
+    async fn stream(
+        &self,
+        query: &ListQuery,
+    ) -> anyhow::Result<BoxStream<'static, anyhow::Result<T>>> {
+        let repo = self.clone();
+        let query = ListQuery {
+            offset: 0,
+            limit: STREAM_BATCH,
+            ..query.clone()
+        };
+        // Each batch starts after the last row of the previous one, rather than
+        // at an offset that rows created or deleted meanwhile would shift.
+        let batches = stream::try_unfold(Some(None), move |after: Option<Option<Keyset>>| {
+            let repo = repo.clone();
+            let query = query.clone();
+            async move {
+                let Some(after) = after else {
+                    return Ok::<_, anyhow::Error>(None);
+                };
+                let batch: Vec<(T, Keyset)> = repo
+                    .call(move |conn, table| select(conn, table, &query, after.as_ref()))
+                    .await?;
+                let next = (batch.len() as u64 == STREAM_BATCH)
+                    .then(|| batch.last().map(|(_, keyset)| keyset.clone()));
+                let batch = batch
+                    .into_iter()
+                    .map(|(item, _)| Ok::<T, anyhow::Error>(item));
+                Ok(Some((stream::iter(batch), next)))
+            }
+        });
+        Ok(batches.try_flatten().boxed())
+    }
+
+    async fn get(&self, id: i32) -> anyhow::Result<Option<T>> {
+        self.call(move |conn, table| {
+            let doc: Option<String> = conn
+                .query_row(
+                    &format!("SELECT doc FROM \"{table}\" WHERE id = ?1"),
+                    [id],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            Ok(doc.map(|doc| serde_json::from_str(&doc)).transpose()?)
+        })
+        .await
+    }
+
+    async fn ids(&self) -> anyhow::Result<Vec<i32>> {
+        self.call(|conn, table| {
+            let mut stmt = conn.prepare(&format!("SELECT id FROM \"{table}\" ORDER BY id"))?;
+            let ids = stmt.query_map([], |row| row.get(0))?;
+            Ok(ids.collect::<Result<_, _>>()?)
+        })
+        .await
+    }
+
+    async fn count(&self) -> anyhow::Result<u64> {
+        self.call(count).await
+    }
+
+    async fn next_id(&self) -> anyhow::Result<i32> {
+        let counters = self.counters.clone();
+        self.call(move |conn, table| {
+            Ok(conn.query_row(
+                &format!("UPDATE \"{counters}\" SET seq = seq + 1 WHERE name = ?1 RETURNING seq"),
+                [table],
+                |row| row.get(0),
+            )?)
+        })
+        .await
+    }
+
+    async fn create(&self, item: T) -> anyhow::Result<T> {
+        let doc = serde_json::to_string(&item)?;
+        let id = item.id();
+        let title = item.title().to_owned();
+        let (index, counters) = (self.index.clone(), self.counters.clone());
+        self.call(move |conn, table| {
+            let inserted = conn.execute(
+                &format!("INSERT OR IGNORE INTO \"{table}\" (id, doc) VALUES (?1, ?2)"),
+                params![id, doc],
+            )?;
+            if inserted == 0 {
+                return Err(Conflict::id(id).into());
+            }
+            conn.execute(
+                &format!("UPDATE \"{counters}\" SET seq = MAX(seq, ?2) WHERE name = ?1"),
+                params![table, id],
+            )?;
+            index.write().unwrap().insert(id, &title);
+            Ok(())
+        })
+        .await?;
+        Ok(item)
+    }
+
+    async fn update(&self, id: i32, item: T) -> anyhow::Result<Option<T>> {
+        let doc = serde_json::to_string(&item)?;
+        let title = item.title().to_owned();
+        let index = self.index.clone();
+        let changed = self
+            .call(move |conn, table| {
+                let changed = conn.execute(
+                    &format!("UPDATE \"{table}\" SET doc = ?2 WHERE id = ?1"),
+                    params![id, doc],
+                )?;
+                if changed > 0 {
+                    index.write().unwrap().insert(id, &title);
+                }
+                Ok(changed)
+            })
+            .await?;
+        Ok((changed > 0).then_some(item))
+    }
+
+    async fn delete(&self, id: i32) -> anyhow::Result<bool> {
+        let index = self.index.clone();
+        let changed = self
+            .call(move |conn, table| {
+                index.write().unwrap().remove(id);
+                Ok(conn.execute(&format!("DELETE FROM \"{table}\" WHERE id = ?1"), [id])?)
+            })
+            .await?;
+        Ok(changed > 0)
+    }
+
+    async fn tags(&self) -> anyhow::Result<Vec<TagCount>> {
+        self.call(|conn, table| {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT tag, COUNT(*) FROM \"{table}.tags\" GROUP BY tag ORDER BY tag"
+            ))?;
+            let tags = stmt.query_map([], |row| {
+                Ok(TagCount {
+                    tag: row.get(0)?,
+                    count: row.get(1)?,
+                })
+            })?;
+            Ok(tags.collect::<Result<_, _>>()?)
+        })
+        .await
+    }
+
+    async fn search(&self, query: &str, limit: usize) -> anyhow::Result<Vec<(T, f64)>> {
+        let ranked = self.index.read().unwrap().search(query, limit);
+        let mut found = Vec::with_capacity(ranked.len());
+        for (id, score) in ranked {
+            // Deleted between ranking and fetching.
+            if let Some(item) = self.get(id).await? {
+                found.push((item, score));
+            }
+        }
+        Ok(found)
+    }
+
+    async fn import(&self, items: Vec<T>, upsert: bool) -> anyhow::Result<Imported> {
+        let rows = (items.iter())
+            .map(|item| {
+                Ok((
+                    item.id(),
+                    item.title().to_owned(),
+                    serde_json::to_string(item)?,
+                ))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let (index, counters) = (self.index.clone(), self.counters.clone());
+        self.call(move |conn, table| {
+            // Rolled back when dropped without a commit.
+            let tx = conn.unchecked_transaction()?;
+            let mut imported = Imported {
+                atomic: true,
+                ..Imported::default()
+            };
+            for (id, _, doc) in &rows {
+                let replaced = upsert
+                    && tx.execute(
+                        &format!("UPDATE \"{table}\" SET doc = ?2 WHERE id = ?1"),
+                        params![id, doc],
+                    )? > 0;
+                if replaced {
+                    imported.replaced += 1;
+                    continue;
+                }
+                let inserted = tx.execute(
+                    &format!("INSERT OR IGNORE INTO \"{table}\" (id, doc) VALUES (?1, ?2)"),
+                    params![id, doc],
+                )?;
+                if inserted == 0 {
+                    return Err(Conflict::id(*id).into());
+                }
+                imported.created += 1;
+            }
+            if let Some(last) = rows.iter().map(|(id, _, _)| *id).max() {
+                tx.execute(
+                    &format!("UPDATE \"{counters}\" SET seq = MAX(seq, ?2) WHERE name = ?1"),
+                    params![table, last],
+                )?;
+            }
+            tx.commit()?;
+
+            let mut index = index.write().unwrap();
+            for (id, title, _) in &rows {
+                index.insert(*id, title);
+            }
+            Ok(imported)
+        })
+        .await
+    }
+}
+
+/// A table of `(key, doc)` rows.
+pub struct SqliteKeyedRepo<V> {
+    conn: Arc<Mutex<Connection>>,
+    table: Arc<str>,
+    _doc: PhantomData<fn() -> V>,
+}
+
+impl<V> SqliteKeyedRepo<V> {
+    fn new(conn: Arc<Mutex<Connection>>, table: &str) -> Self {
+        Self {
+            conn,
+            table: table.into(),
+            _doc: PhantomData,
+        }
+    }
+}
+
+#[async_trait]
+impl<V: Keyed> KeyedRepo<V> for SqliteKeyedRepo<V> {
+    async fn get(&self, key: &str) -> anyhow::Result<Option<V>> {
+        let key = key.to_owned();
+        run(&self.conn, &self.table, move |conn, table| {
+            let doc: Option<String> = conn
+                .query_row(
+                    &format!("SELECT doc FROM \"{table}\" WHERE key = ?1"),
+                    [key],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            Ok(doc.map(|doc| serde_json::from_str(&doc)).transpose()?)
+        })
+        .await
+    }
+
+    async fn insert(&self, value: V) -> anyhow::Result<()> {
+        let doc = serde_json::to_string(&value)?;
+        let key = value.key().to_owned();
+        run(&self.conn, &self.table, move |conn, table| {
+            let inserted = conn.execute(
+                &format!("INSERT OR IGNORE INTO \"{table}\" (key, doc) VALUES (?1, ?2)"),
+                params![key, doc],
+            )?;
+            match inserted {
+                0 => Err(Conflict::key::<V>(&key).into()),
+                _ => Ok(()),
+            }
+        })
+        .await
+    }
+
+    async fn replace(&self, value: V) -> anyhow::Result<bool> {
+        let doc = serde_json::to_string(&value)?;
+        let key = value.key().to_owned();
+        let changed = run(&self.conn, &self.table, move |conn, table| {
+            Ok(conn.execute(
+                &format!("UPDATE \"{table}\" SET doc = ?2 WHERE key = ?1"),
+                params![key, doc],
+            )?)
+        })
+        .await?;
+        Ok(changed > 0)
+    }
+
+    async fn remove(&self, key: &str) -> anyhow::Result<bool> {
+        let key = key.to_owned();
+        let changed = run(&self.conn, &self.table, move |conn, table| {
+            Ok(conn.execute(&format!("DELETE FROM \"{table}\" WHERE key = ?1"), [key])?)
+        })
+        .await?;
+        Ok(changed > 0)
+    }
+
+    async fn list(&self) -> anyhow::Result<Vec<V>> {
+        run(&self.conn, &self.table, |conn, table| {
+            let mut stmt = conn.prepare(&format!("SELECT doc FROM \"{table}\" ORDER BY key"))?;
+            let docs = stmt.query_map([], |row| row.get::<_, String>(0))?;
+            let mut values = Vec::new();
+            for doc in docs {
+                values.push(serde_json::from_str(&doc?)?);
+            }
+            Ok(values)
+        })
+        .await
+    }
+
+    async fn count(&self) -> anyhow::Result<u64> {
+        run(&self.conn, &self.table, count).await
+    }
+}
+
+fn count(conn: &Connection, table: &str) -> anyhow::Result<u64> {
+    let sql = format!("SELECT COUNT(*) FROM \"{table}\"");
+    Ok(conn.query_row(&sql, [], |row| row.get(0))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fact(id: i32, title: &str) -> Entry {
+        Entry::new(Kind::new("facts").unwrap(), id, title).unwrap()
+    }
+
+    /// The entries of `kind`, in a table of the same name.
+    async fn open(store: &SqliteStore, kind: &str) -> Arc<dyn Repo<Entry>> {
+        let kind = Kind::new(kind).unwrap();
+        store.entries(&kind, kind.as_str()).await.unwrap()
+    }
+
+    #[actix_web::test]
+    async fn round_trips_records() {
+        let store = SqliteStore::open(":memory:", &Collections::default()).unwrap();
+        let repo = open(&store, "facts").await;
+        for (id, title) in [(2, "Dogs bark"), (1, "Cats purr")] {
+            repo.create(fact(id, title)).await.unwrap();
+        }
+        let all = ListQuery::default();
+        let duplicate = repo.create(fact(1, "Birds sing")).await.unwrap_err();
+        assert!(duplicate.is::<Conflict>());
+        assert_eq!(repo.next_id().await.unwrap(), 3);
+        assert_eq!(repo.next_id().await.unwrap(), 4);
+        let principles = open(&store, "principles").await;
+        assert_eq!(principles.next_id().await.unwrap(), 1);
+        assert_eq!(
+            repo.list(&all).await.unwrap().0,
+            [fact(1, "Cats purr"), fact(2, "Dogs bark")]
+        );
+
+        let query = ListQuery {
+            offset: 1,
+            limit: 1,
+            sort: SortKey::Title,
+            descending: true,
+            ..ListQuery::default()
+        };
+        let page = (vec![fact(1, "Cats purr")], 2);
+        assert_eq!(repo.list(&query).await.unwrap(), page);
+        let query = ListQuery {
+            title: Some("DOG".to_owned()),
+            ..ListQuery::default()
+        };
+        let page = (vec![fact(2, "Dogs bark")], 1);
+        assert_eq!(repo.list(&query).await.unwrap(), page);
+        assert_eq!(repo.get(1).await.unwrap(), Some(fact(1, "Cats purr")));
+        assert!(principles.list(&all).await.unwrap().0.is_empty());
+
+        let updated = repo.update(2, fact(2, "Dogs nap")).await.unwrap();
+        assert_eq!(updated, Some(fact(2, "Dogs nap")));
+        assert_eq!(repo.update(3, fact(3, "Birds sing")).await.unwrap(), None);
+
+        assert!(repo.delete(1).await.unwrap());
+        assert!(!repo.delete(1).await.unwrap());
+        assert_eq!(repo.get(1).await.unwrap(), None);
+        assert_eq!(repo.list(&all).await.unwrap().0, [fact(2, "Dogs nap")]);
+    }
+
+    #[actix_web::test]
+    async fn kinds_may_be_sql_keywords() {
+        let store = SqliteStore::open(":memory:", &Collections::default()).unwrap();
+        let repo = open(&store, "order").await;
+        let id = repo.next_id().await.unwrap();
+        let mut entry = Entry::new(Kind::new("order").unwrap(), id, "Pay the rent").unwrap();
+        entry.tags = vec!["home".to_owned()];
+        repo.create(entry).await.unwrap();
+
+        let (entries, total) = repo.list(&ListQuery::default()).await.unwrap();
+        assert_eq!((entries.len(), total), (1, 1));
+        assert_eq!(repo.tags().await.unwrap().len(), 1);
+        assert!(repo.delete(id).await.unwrap());
+        assert!(repo.tags().await.unwrap().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn filters_by_all_or_any_tag() {
+        let store = SqliteStore::open(":memory:", &Collections::default()).unwrap();
+        let repo = open(&store, "facts").await;
+        for (id, tags) in [
+            (1, vec!["health", "sleep"]),
+            (2, vec!["health"]),
+            (3, vec![]),
+        ] {
+            let mut entry = fact(id, "Sleep well");
+            entry.tags = tags.into_iter().map(str::to_owned).collect();
+            repo.create(entry).await.unwrap();
+        }
+        let query = |any_tag| ListQuery {
+            tags: vec!["health".to_owned(), "sleep".to_owned()],
+            any_tag,
+            descending: true,
+            ..ListQuery::default()
+        };
+        let ids = |entries: Vec<Entry>| entries.iter().map(|entry| entry.id).collect::<Vec<_>>();
+
+        let (all, total) = repo.list(&query(false)).await.unwrap();
+        assert_eq!((ids(all), total), (vec![1], 1));
+        let (any, total) = repo.list(&query(true)).await.unwrap();
+        assert_eq!((ids(any), total), (vec![2, 1], 2));
+        // Streams page by keyset, bound after the tag parameters.
+        let streamed = repo.stream(&query(true)).await.unwrap();
+        assert_eq!(ids(streamed.try_collect().await.unwrap()), [2, 1]);
+    }
+
+    #[actix_web::test]
+    async fn streams_past_rows_deleted_between_batches() {
+        let store = SqliteStore::open(":memory:", &Collections::default()).unwrap();
+        let repo = open(&store, "facts").await;
+        let titles = ["Cats purr", "Dogs bark", "Birds sing"];
+        for id in 1..=STREAM_BATCH as i32 * 2 + 1 {
+            let title = titles[id as usize % titles.len()];
+            repo.create(fact(id, title)).await.unwrap();
+        }
+        let query = ListQuery {
+            limit: 1000,
+            sort: SortKey::Title,
+            descending: true,
+            ..ListQuery::default()
+        };
+        let (all, _) = repo.list(&query).await.unwrap();
+
+        let mut stream = repo.stream(&query).await.unwrap();
+        let mut streamed = Vec::new();
+        for _ in 0..STREAM_BATCH {
+            streamed.push(stream.next().await.unwrap().unwrap());
+        }
+        assert!(repo.delete(all[0].id).await.unwrap());
+        streamed.extend(stream.try_collect::<Vec<_>>().await.unwrap());
+        assert_eq!(streamed, all);
+    }
+}
@@ -0,0 +1,155 @@
+//! `error` is what goes wrong in dio-server, and how clients are told.
+//!
+//! Handlers and middleware fail with a [`DioError`], rendered as an RFC 7807
+//! `application/problem+json` body. Its `code` member is stable, so clients can
+//! branch on it rather than on `detail`, which is meant for people.
+
+use crate::{bulk::RowError, db::Conflict, model::Role};
+use actix_web::{
+    error::BlockingError,
+    http::{header, StatusCode},
+    HttpResponse, ResponseError,
+};
+use serde_json::json;
+
+const PROBLEM_JSON: &str = "application/problem+json";
+
+#[derive(Debug, thiserror::Error)]
+pub enum DioError {
+    /// The addressed entry, user or key does not exist.
+    #[error("{0}")]
+    NotFound(String),
+
+    /// The request is malformed or breaks a rule on its values.
+    #[error("{0}")]
+    Validation(String),
+
+    /// A well-formed body does not describe a valid record.
+    #[error("{0}")]
+    Invalid(String),
+
+    /// Rows of an import are invalid, so none were written. Holds the first
+    /// errors, up to a limit, and how many rows are invalid in all.
+    #[error("Invalid rows: {invalid}, nothing was imported")]
+    InvalidRows {
+        invalid: usize,
+        errors: Vec<RowError>,
+    },
+
+    /// Something with the same id or key already exists.
+    #[error("{0}")]
+    Conflict(String),
+
+    /// No valid credentials were sent.
+    #[error("{0}")]
+    Unauthorized(String),
+
+    /// The credentials are valid but their role is too low.
+    #[error("This needs the {required} role")]
+    Forbidden { required: Role, role: Role },
+
+    /// The client used up its rate limit budget.
+    #[error("Too many requests, retry in {retry_after}s")]
+    RateLimited { retry_after: u64 },
+
+    /// The storage backend failed. Only logged, never shown to clients.
+    #[error("Storage error: {0:#}")]
+    Storage(anyhow::Error),
+
+    /// The server is misconfigured.
+    #[error("{0}")]
+    Config(String),
+}
+
+impl DioError {
+    /// Stable identifier of the kind of error, the `code` of problem bodies.
+    pub fn code(&self) -> &'static str {
+        match self {
+            DioError::NotFound(_) => "not_found",
+            DioError::Validation(_) => "invalid_request",
+            DioError::Invalid(_) => "invalid_record",
+            DioError::InvalidRows { .. } => "invalid_rows",
+            DioError::Conflict(_) => "conflict",
+            DioError::Unauthorized(_) => "unauthorized",
+            DioError::Forbidden { .. } => "forbidden",
+            DioError::RateLimited { .. } => "rate_limited",
+            DioError::Storage(_) => "storage_error",
+            DioError::Config(_) => "config_error",
+        }
+    }
+
+    /// What clients are told. Storage failures can carry driver messages,
+    /// so they are replaced with a generic one.
+    pub fn detail(&self) -> String {
+        match self {
+            DioError::Storage(_) => "The database could not complete the request".to_owned(),
+            err => err.to_string(),
+        }
+    }
+}
+
+impl From<anyhow::Error> for DioError {
+    /// Keeps [`Conflict`]s raised by stores apart from other storage failures.
+    fn from(err: anyhow::Error) -> Self {
+        match err.downcast::<Conflict>() {
+            Ok(conflict) => DioError::Conflict(conflict.to_string()),
+            Err(err) => DioError::Storage(err),
+        }
+    }
+}
+
+impl From<BlockingError> for DioError {
+    fn from(err: BlockingError) -> Self {
+        DioError::Storage(err.into())
+    }
+}
+
+impl ResponseError for DioError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            DioError::NotFound(_) => StatusCode::NOT_FOUND,
+            DioError::Validation(_) => StatusCode::BAD_REQUEST,
+            DioError::Invalid(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            DioError::InvalidRows { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            DioError::Conflict(_) => StatusCode::CONFLICT,
+            DioError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            DioError::Forbidden { .. } => StatusCode::FORBIDDEN,
+            DioError::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+            DioError::Storage(_) | DioError::Config(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        if let DioError::Storage(_) | DioError::Config(_) = self {
+            tracing::error!(code = self.code(), error = %self, "request failed");
+        }
+        let status = self.status_code();
+        let mut problem = json!({
+            "type": format!("urn:dio:problem:{}", self.code()),
+            "title": status.canonical_reason().unwrap_or_default(),
+            "status": status.as_u16(),
+            "detail": self.detail(),
+            "code": self.code(),
+        });
+
+        let mut res = HttpResponse::build(status);
+        match self {
+            DioError::Unauthorized(_) => {
+                res.insert_header((header::WWW_AUTHENTICATE, "Bearer"));
+            }
+            DioError::Forbidden { required, role } => {
+                problem["required_role"] = json!(required);
+                problem["role"] = json!(role);
+            }
+            DioError::InvalidRows { errors, .. } => {
+                problem["errors"] = json!(errors);
+            }
+            DioError::RateLimited { retry_after } => {
+                res.insert_header((header::RETRY_AFTER, *retry_after));
+                problem["retry_after"] = json!(retry_after);
+            }
+            _ => {}
+        }
+        res.content_type(PROBLEM_JSON).body(problem.to_string())
+    }
+}
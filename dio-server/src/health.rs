@@ -0,0 +1,106 @@
+//! `health` answers liveness and readiness probes.
+//!
+//! `/health/live` only says the process serves requests. `/health/ready` also
+//! pings the store, and answers `503 Service Unavailable` while it is down or
+//! still coming up, so load balancers stop routing to the instance.
+
+use crate::db::DioStore;
+use actix_web::{rt::time, web, HttpResponse};
+use serde_json::json;
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+/// Longest wait for the store to answer a readiness probe.
+const PING_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Whether the database answered at startup, see `db::connect`, and the server
+/// is not shutting down, and since when the server runs.
+#[derive(Debug)]
+pub struct Readiness {
+    ready: AtomicBool,
+    started: Instant,
+}
+
+impl Default for Readiness {
+    fn default() -> Self {
+        Self {
+            ready: AtomicBool::new(false),
+            started: Instant::now(),
+        }
+    }
+}
+
+impl Readiness {
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Acquire)
+    }
+
+    /// Cleared again on shutdown, so load balancers stop routing here.
+    pub fn set_ready(&self, ready: bool) {
+        self.ready.store(ready, Ordering::Release);
+    }
+}
+
+/// Registers the probes. They sit outside rate limiting and authentication.
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.route("/health/live", web::get().to(live)).service(
+        // `/healthcheck` predates the split probes.
+        web::resource(["/health/ready", "/healthcheck"]).route(web::get().to(ready)),
+    );
+}
+
+#[utoipa::path(
+    get,
+    path = "/health/live",
+    tag = "health",
+    responses((status = 200, body = Object, example = json!({ "status": "ok" })))
+)]
+async fn live() -> HttpResponse {
+    HttpResponse::Ok().json(json!({ "status": "ok" }))
+}
+
+/// Also served at `/healthcheck`.
+#[utoipa::path(
+    get,
+    path = "/health/ready",
+    tag = "health",
+    responses(
+        (status = 200, description = "The database answered", body = Object),
+        (status = 503, description = "The database is down or still coming up", body = Object),
+    )
+)]
+async fn ready(store: web::Data<dyn DioStore>, readiness: web::Data<Readiness>) -> HttpResponse {
+    let database = if readiness.is_ready() {
+        let started = Instant::now();
+        let ping = time::timeout(PING_TIMEOUT, store.ping()).await;
+        let latency_ms = (started.elapsed().as_secs_f64() * 1e6).round() / 1e3;
+        match ping {
+            Ok(Ok(())) => json!({ "status": "ok", "latency_ms": latency_ms }),
+            Ok(Err(err)) => {
+                tracing::warn!(
+                    error = format!("{err:#}"),
+                    "readiness probe: database failed"
+                );
+                json!({ "status": "down", "latency_ms": latency_ms })
+            }
+            Err(_) => json!({ "status": "down", "latency_ms": latency_ms, "timed_out": true }),
+        }
+    } else {
+        json!({ "status": "starting" })
+    };
+
+    let up = database["status"] == "ok";
+    let body = json!({
+        "status": if up { "ok" } else { "unavailable" },
+        "version": env!("CARGO_PKG_VERSION"),
+        "git_hash": env!("DIO_GIT_HASH"),
+        "uptime_secs": readiness.started.elapsed().as_secs(),
+        "checks": { "database": database },
+    });
+    match up {
+        true => HttpResponse::Ok().json(body),
+        false => HttpResponse::ServiceUnavailable().json(body),
+    }
+}
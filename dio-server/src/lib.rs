@@ -1,3 +1,13 @@
+//! Default database and collection names, overridable through `config::Config`.
+
 pub const DB_NAME: &str = "dio";
-pub const COLL_NAME_FACTS: &str = "facts";
-pub const COLL_NAME_PRINCIPLES: &str = "principles";
+/// Entry kinds served unless the `kinds` setting says otherwise. Each is kept
+/// in a collection of the same name by default.
+pub const KINDS: [&str; 2] = ["facts", "principles"];
+pub const COLL_NAME_USERS: &str = "users";
+pub const COLL_NAME_SESSIONS: &str = "sessions";
+pub const COLL_NAME_API_KEYS: &str = "api_keys";
+/// Holds the kinds registered through `POST /kinds`.
+pub const COLL_NAME_KINDS: &str = "kinds";
+/// Holds one `{ _id: <collection name>, seq: <last id> }` document per collection.
+pub const COLL_NAME_COUNTERS: &str = "counters";
@@ -1,35 +1,188 @@
-//! dio-server is the backend for REST Api integration with MongoDB.
+//! dio-server serves the REST API of dio, keeping its records in a
+//! [`db::DioStore`]: MongoDB, SQLite, or memory for development and tests.
 //!
 //! # TODO:
 
 extern crate dotenv;
 
-use crate::{db::DioDB, route::config};
-use actix_web::{App, HttpServer};
+use crate::{
+    config::{Config, ConfigArgs},
+    db::{DioStore, Kinds, MeteredStore},
+    health::Readiness,
+    metrics::Metrics,
+    ratelimit::RateLimiter,
+    route::config,
+};
+use actix_web::{
+    rt::{self, signal, time},
+    web::Data,
+    App, HttpServer,
+};
+use clap::{Parser, Subcommand};
 use dotenv::dotenv;
-use mongodb::Client;
+use futures::future::{self, Either};
+use std::{path::PathBuf, pin::pin, sync::Arc};
 
+mod admin;
+mod auth;
+mod bulk;
+mod config;
+mod daily;
 mod db;
+mod error;
+mod health;
+mod metrics;
 pub mod model;
+mod openapi;
+mod ratelimit;
 mod route;
+mod search;
+mod seed;
+mod telemetry;
 mod util;
 // #[cfg(test)]
 // mod test;
 
+#[derive(Parser, Debug)]
+#[command(version, about = "REST API for dio facts and principles")]
+struct Cli {
+    #[command(flatten)]
+    config: ConfigArgs,
+
+    /// Serves the API when omitted.
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Creates an admin account, reading its password from the first line of
+    /// standard input, e.g. `dio-server admin boss < password.txt`.
+    Admin {
+        username: String,
+
+        /// Make an existing account admin instead, keeping its password.
+        #[arg(long)]
+        promote: bool,
+    },
+
+    /// Creates or updates entries from a dio-cli `data.json` file, giving the
+    /// n-th entry of each list id n, and registers kinds the server does not
+    /// know yet. Safe to run again after edits.
+    Seed {
+        /// Path to the file, shaped `{"facts": [...], "principles": [...]}`.
+        file: PathBuf,
+
+        /// Also delete records whose id is past the end of their list.
+        #[arg(long)]
+        prune: bool,
+    },
+}
+
 #[actix_web::main]
-async fn main() -> std::io::Result<()> {
+async fn main() -> anyhow::Result<()> {
     dotenv().ok();
+    let cli = Cli::parse();
+    let settings = Config::load(&cli.config)?;
+    telemetry::init(&settings);
+    if let Some(Command::Seed { file, prune }) = &cli.command {
+        return seed::run(&settings, file, *prune).await;
+    }
+
+    let metrics = Data::new(Metrics::new());
+    let store: Arc<dyn DioStore> = Arc::new(MeteredStore::new(
+        db::init_store(&settings).await?,
+        metrics.clone().into_inner(),
+    ));
+    if let Some(Command::Admin { username, promote }) = &cli.command {
+        db::connect(store.as_ref(), &settings).await?;
+        let stdin = std::io::stdin().lock();
+        let done = admin::run(store.as_ref(), &settings.auth, username, *promote, stdin).await?;
+        println!("{done}");
+        return Ok(());
+    }
+    let kinds = Data::new(Kinds::open(store.clone(), &settings).await?);
+    let auth_settings = Data::new(settings.auth.clone());
+    let rate_limiter = Data::new(RateLimiter::new(settings.rate_read, settings.rate_write));
+    let readiness = Data::new(Readiness::default());
+    tracing::info!(bind = %settings.bind, port = settings.port, "starting server");
+
+    let mut server = HttpServer::new({
+        let store = store.clone();
+        let kinds = kinds.clone();
+        let readiness = readiness.clone();
+        move || {
+            App::new()
+                .app_data(Data::from(store.clone()))
+                .app_data(kinds.clone())
+                .app_data(readiness.clone())
+                .app_data(metrics.clone())
+                .app_data(auth_settings.clone())
+                .app_data(rate_limiter.clone())
+                .configure(|cfg| config(cfg, &kinds))
+        }
+    });
+    if let Some(workers) = settings.workers {
+        server = server.workers(workers);
+    }
+    let server = (server.disable_signals())
+        .shutdown_timeout(settings.shutdown_timeout.as_secs())
+        .bind((settings.bind.as_str(), settings.port))?
+        .run();
+
+    let handle = server.handle();
+    rt::spawn({
+        let readiness = readiness.clone();
+        async move {
+            let signal = shutdown_signal().await;
+            tracing::info!(signal, "shutting down, draining in-flight requests");
+            readiness.set_ready(false);
+            handle.stop(true).await;
+        }
+    });
+
+    // Serve health checks while the database comes up, and stop if it never does.
+    let connect = async {
+        db::connect(store.as_ref(), &settings).await?;
+        kinds.prepare().await?;
+        readiness.set_ready(true);
+        future::pending::<anyhow::Result<()>>().await
+    };
+    match future::select(pin!(server), pin!(connect)).await {
+        Either::Left((stopped, _)) => stopped?,
+        Either::Right((failed, _)) => return failed,
+    }
+
+    tracing::info!("stopped serving, closing the database connection");
+    if time::timeout(settings.shutdown_timeout, store.close())
+        .await
+        .is_err()
+    {
+        tracing::warn!("the database connection did not close in time");
+    }
+    tracing::info!("shut down");
+    Ok(())
+}
 
-    let db_client: Client = DioDB::init().await;
-    const PORT: u16 = 5000;
-    println!("Starting server on PORT {}", PORT);
-
-    HttpServer::new(move || {
-        App::new()
-            .app_data(actix_web::web::Data::new(db_client.clone()))
-            .configure(config)
-    })
-    .bind(("127.0.0.1", PORT))?
-    .run()
-    .await
+/// Resolves on the first SIGTERM or SIGINT, naming it.
+async fn shutdown_signal() -> &'static str {
+    let interrupt = async {
+        signal::ctrl_c().await.expect("SIGINT can be listened for");
+        "SIGINT"
+    };
+    #[cfg(unix)]
+    {
+        let mut sigterm = signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("SIGTERM can be listened for");
+        let terminate = async move {
+            sigterm.recv().await;
+            "SIGTERM"
+        };
+        future::select(pin!(interrupt), pin!(terminate))
+            .await
+            .factor_first()
+            .0
+    }
+    #[cfg(not(unix))]
+    interrupt.await
 }
@@ -0,0 +1,271 @@
+//! `metrics` collects Prometheus metrics and serves them at `/metrics`.
+//!
+//! [`Measure`] records every request passing through `route::config`, labelled
+//! with the name of the handler that answered it, as set by [`Handler`].
+//! `db::MeteredStore` times storage operations, and document counts are read
+//! from the store on every scrape.
+
+use crate::{
+    db::{DioStore, Kinds},
+    error::DioError,
+};
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::ContentType,
+    web, HttpMessage, HttpRequest, HttpResponse,
+};
+use futures::future::{ready, LocalBoxFuture, Ready};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+use std::{rc::Rc, time::Instant};
+
+/// Label of requests that matched no route.
+const UNMATCHED: &str = "unmatched";
+
+/// Every metric of the server, registered as app data.
+pub struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    request_duration: HistogramVec,
+    in_flight: IntGauge,
+    db_duration: HistogramVec,
+    db_errors: IntCounterVec,
+    documents: IntGaugeVec,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let requests = IntCounterVec::new(
+            Opts::new("dio_http_requests_total", "HTTP requests answered"),
+            &["handler", "method", "status"],
+        )
+        .unwrap();
+        let request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "dio_http_request_duration_seconds",
+                "Time to answer HTTP requests",
+            ),
+            &["handler", "method"],
+        )
+        .unwrap();
+        let in_flight = IntGauge::new(
+            "dio_http_requests_in_flight",
+            "HTTP requests being answered",
+        )
+        .unwrap();
+        let db_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "dio_db_operation_duration_seconds",
+                "Time taken by storage operations",
+            )
+            .buckets(vec![
+                0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5,
+            ]),
+            &["collection", "operation"],
+        )
+        .unwrap();
+        let db_errors = IntCounterVec::new(
+            Opts::new("dio_db_operation_errors_total", "Failed storage operations"),
+            &["collection", "operation"],
+        )
+        .unwrap();
+        let documents = IntGaugeVec::new(
+            Opts::new("dio_collection_documents", "Documents per collection"),
+            &["collection"],
+        )
+        .unwrap();
+
+        let registry = Registry::new();
+        registry.register(Box::new(requests.clone())).unwrap();
+        registry
+            .register(Box::new(request_duration.clone()))
+            .unwrap();
+        registry.register(Box::new(in_flight.clone())).unwrap();
+        registry.register(Box::new(db_duration.clone())).unwrap();
+        registry.register(Box::new(db_errors.clone())).unwrap();
+        registry.register(Box::new(documents.clone())).unwrap();
+        Self {
+            registry,
+            requests,
+            request_duration,
+            in_flight,
+            db_duration,
+            db_errors,
+            documents,
+        }
+    }
+
+    /// Records one storage operation that took `seconds`.
+    pub fn observe_db(&self, collection: &str, operation: &str, seconds: f64, failed: bool) {
+        let labels = [collection, operation];
+        self.db_duration.with_label_values(&labels).observe(seconds);
+        if failed {
+            self.db_errors.with_label_values(&labels).inc();
+        }
+    }
+}
+
+/// Registers `/metrics`, outside rate limiting and authentication.
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.route("/metrics", web::get().to(scrape));
+}
+
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "meta",
+    responses((status = 200, description = "Prometheus text format", body = String))
+)]
+async fn scrape(
+    metrics: web::Data<Metrics>,
+    store: web::Data<dyn DioStore>,
+    kinds: web::Data<Kinds>,
+) -> Result<HttpResponse, DioError> {
+    let mut counts = Vec::new();
+    for (kind, repo) in kinds.all() {
+        counts.push((kind.to_string(), repo.count().await?));
+    }
+    counts.extend([
+        ("users".to_owned(), store.users().count().await?),
+        ("sessions".to_owned(), store.sessions().count().await?),
+        ("api_keys".to_owned(), store.api_keys().count().await?),
+        ("kinds".to_owned(), store.kind_defs().count().await?),
+    ]);
+    for (collection, count) in counts {
+        (metrics.documents.with_label_values(&[&collection])).set(count as i64);
+    }
+
+    let mut body = Vec::new();
+    (TextEncoder::new().encode(&metrics.registry.gather(), &mut body))
+        .map_err(|e| DioError::Storage(e.into()))?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::plaintext())
+        .body(body))
+}
+
+/// The handler that answered `req`. Requests turned away before reaching one,
+/// e.g. by rate limiting, are labelled with the pattern of their route instead.
+fn handler_name(req: &HttpRequest) -> String {
+    let name = req.extensions().get::<HandlerName>().map(|name| name.0);
+    (name.or_else(|| req.match_name()))
+        .map(str::to_owned)
+        .or_else(|| req.match_pattern())
+        .unwrap_or_else(|| UNMATCHED.to_owned())
+}
+
+/// The handler that answered a request, stored in its extensions by [`Handler`].
+#[derive(Clone, Copy, Debug)]
+struct HandlerName(&'static str);
+
+/// Route middleware naming the handler of the route for [`Measure`].
+/// Resources from the `#[get]`-style macros are named after their handler
+/// already, so only `web::route()`s need it.
+pub struct Handler(pub &'static str);
+
+impl<S, B> Transform<S, ServiceRequest> for Handler
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = HandlerMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(HandlerMiddleware {
+            service,
+            name: self.0,
+        }))
+    }
+}
+
+pub struct HandlerMiddleware<S> {
+    service: S,
+    name: &'static str,
+}
+
+impl<S, B> Service<ServiceRequest> for HandlerMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = S::Future;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        req.extensions_mut().insert(HandlerName(self.name));
+        self.service.call(req)
+    }
+}
+
+/// Middleware recording the count, status and duration of every request, and
+/// how many are in flight. Reads the [`Metrics`] registered as app data.
+pub struct Measure;
+
+impl<S, B> Transform<S, ServiceRequest> for Measure
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = MeasureMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(MeasureMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct MeasureMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for MeasureMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        Box::pin(async move {
+            let metrics = (req.app_data::<web::Data<Metrics>>().cloned())
+                .expect("Metrics are registered as app data");
+            let method = req.method().to_string();
+            let started = Instant::now();
+            metrics.in_flight.inc();
+            let res = service.call(req).await;
+            metrics.in_flight.dec();
+
+            let (status, handler) = match &res {
+                Ok(res) => (res.status(), handler_name(res.request())),
+                Err(err) => (err.as_response_error().status_code(), UNMATCHED.to_owned()),
+            };
+            (metrics
+                .request_duration
+                .with_label_values(&[&handler, &method]))
+            .observe(started.elapsed().as_secs_f64());
+            (metrics
+                .requests
+                .with_label_values(&[&handler, &method, status.as_str()]))
+            .inc();
+            res
+        })
+    }
+}
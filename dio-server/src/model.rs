@@ -1,34 +1,249 @@
-//! 'model' contain MongoDB database models.
-
-use mongodb::bson::oid::ObjectId;
-use serde::{Deserialize, Serialize};
-
-/// The snippet above does the following:
-///
-/// Imports the required dependencies
-/// Uses the derive macro to generate implementation support for formatting the output, serializing, and deserializing the data structure.
-/// Creates a User struct with required properties. We also added field attributes to the id property to rename and ignore the field if it is empty.
-/// PS: The pub modifier makes the struct and its property public and can be accessed from other files/modules.
-#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
-pub struct Facts {
-    /// MONGODB UUID Auto gen.
-    // #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
-    // pub _id: Option<ObjectId>,
+//! `model` contains the records kept in a [`crate::db::DioStore`], whichever
+//! backend stores them.
 
+use crate::search::Span;
+use chrono::{DateTime, Utc};
+use dio_core::ValidationError;
+pub use dio_core::{Entry, Kind};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::fmt;
+use utoipa::ToSchema;
+
+/// Behaviour shared by every document kept in a [`crate::db::DioStore`].
+pub trait Record: Clone + Send + Sync + Unpin + Serialize + DeserializeOwned + 'static {
     /// Index of the item.
-    pub id: i32,
+    fn id(&self) -> i32;
+
+    fn set_id(&mut self, id: i32);
+
+    fn title(&self) -> &str;
+
+    /// Sorted, without duplicates.
+    fn tags(&self) -> &[String];
+
+    fn created_at(&self) -> Option<DateTime<Utc>>;
 
-    pub title: String,
+    fn set_created_at(&mut self, at: DateTime<Utc>);
+
+    fn set_updated_at(&mut self, at: DateTime<Utc>);
+
+    /// Normalizes the fields clients send, then checks them.
+    fn normalize(&mut self) -> Result<(), ValidationError>;
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
-pub struct Principles {
-    /// MONGODB UUID Auto gen.
-    // #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
-    // pub _id: Option<ObjectId>,
+/// Behaviour shared by documents looked up by a unique string key.
+pub trait Keyed: Clone + Send + Sync + Unpin + Serialize + DeserializeOwned + 'static {
+    /// Name of the key field.
+    const KEY: &'static str;
 
-    /// Index of the item.
-    pub id: i32,
+    /// What a document is called in error messages.
+    const NOUN: &'static str;
+
+    fn key(&self) -> &str;
+}
+
+impl Record for Entry {
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    fn set_id(&mut self, id: i32) {
+        self.id = id;
+    }
+
+    fn title(&self) -> &str {
+        &self.text
+    }
+
+    fn tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    fn created_at(&self) -> Option<DateTime<Utc>> {
+        self.created_at
+    }
+
+    fn set_created_at(&mut self, at: DateTime<Utc>) {
+        self.created_at = Some(at);
+    }
+
+    fn set_updated_at(&mut self, at: DateTime<Utc>) {
+        self.updated_at = Some(at);
+    }
+
+    fn normalize(&mut self) -> Result<(), ValidationError> {
+        Entry::normalize(self)
+    }
+}
+
+/// An entry kind registered through `POST /kinds`, on top of those configured.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct KindDef {
+    pub kind: Kind,
+
+    /// Username of the admin who registered the kind, or `seed`.
+    pub created_by: String,
+
+    pub created_at: DateTime<Utc>,
+}
+
+impl Keyed for KindDef {
+    const KEY: &'static str = "kind";
+    const NOUN: &'static str = "kind";
+
+    fn key(&self) -> &str {
+        self.kind.as_str()
+    }
+}
+
+/// An account that can sign in to make changes.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct User {
+    pub username: String,
+
+    /// PHC string of the Argon2id hash, which embeds its salt and cost parameters.
+    pub password_hash: String,
+
+    /// Accounts created before roles existed are readers.
+    #[serde(default)]
+    pub role: Role,
+
+    pub created_at: DateTime<Utc>,
+}
+
+impl Keyed for User {
+    const KEY: &'static str = "username";
+    const NOUN: &'static str = "user";
+
+    fn key(&self) -> &str {
+        &self.username
+    }
+}
+
+/// A signed-in user. The token itself is only ever known to the client.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Session {
+    /// SHA-256 of the bearer token, hex encoded.
+    pub token_digest: String,
+
+    pub username: String,
+
+    pub expires_at: DateTime<Utc>,
+}
+
+impl Keyed for Session {
+    const KEY: &'static str = "token_digest";
+    const NOUN: &'static str = "session";
+
+    fn key(&self) -> &str {
+        &self.token_digest
+    }
+}
+
+/// What a user may do. Each role can do everything the roles before it can.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize, ToSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Reads entries, like anonymous clients.
+    #[default]
+    Reader,
+    /// Also creates and updates entries.
+    Editor,
+    /// Also deletes entries and manages users and API keys.
+    Admin,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Role::Reader => "reader",
+            Role::Editor => "editor",
+            Role::Admin => "admin",
+        })
+    }
+}
+
+/// What an API key may do.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Only what anonymous clients may do, but identified.
+    Read,
+    /// Create, change and delete entries.
+    Write,
+}
+
+impl Scope {
+    /// The role a key with this scope acts with.
+    pub fn role(self) -> Role {
+        match self {
+            Scope::Read => Role::Reader,
+            Scope::Write => Role::Editor,
+        }
+    }
+}
+
+/// A long-lived token for machine clients, minted by an admin.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ApiKey {
+    /// Public part of the token, `dio_<id>_<secret>`.
+    pub id: String,
+
+    /// What the key is for, e.g. `nightly-import`.
+    pub name: String,
+
+    pub scope: Scope,
+
+    /// SHA-256 of the whole token, hex encoded.
+    pub token_digest: String,
+
+    /// Username of the admin who minted the key.
+    pub created_by: String,
+
+    pub created_at: DateTime<Utc>,
+}
+
+impl Keyed for ApiKey {
+    const KEY: &'static str = "id";
+    const NOUN: &'static str = "API key";
+
+    fn key(&self) -> &str {
+        &self.id
+    }
+}
+
+/// One page of a list endpoint.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+
+    /// Pass back as `cursor` to fetch the following page. `None` on the last page.
+    pub next_cursor: Option<String>,
+
+    /// Number of records matching the filters, across all pages.
+    pub total: u64,
+}
+
+/// A tag of `GET /{kind}/tags`, with the number of entries carrying it.
+#[derive(Debug, PartialEq, Eq, Serialize, ToSchema)]
+pub struct TagCount {
+    #[schema(example = "health")]
+    pub tag: String,
+    pub count: u64,
+}
+
+/// One result of `GET /search`.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SearchHit {
+    pub kind: Kind,
+
+    /// Relevance, higher is better.
+    pub score: f64,
+
+    /// Words of the title that matched the query.
+    pub matches: Vec<Span>,
 
-    pub title: String,
+    pub item: serde_json::Value,
 }
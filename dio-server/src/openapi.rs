@@ -0,0 +1,281 @@
+//! `openapi` describes the REST API as an OpenAPI 3 document, generated from
+//! the `#[utoipa::path]` attributes of the handlers and the models they use.
+//!
+//! The document is served at `/openapi.json` and browsed at `/docs/`, with a
+//! Swagger UI bundled into the binary so the page works offline.
+
+use crate::{health, metrics, route};
+use actix_web::web;
+use utoipa::{
+    openapi::{
+        security::{Http, HttpAuthScheme, SecurityScheme},
+        Deprecated,
+    },
+    Modify, OpenApi,
+};
+use utoipa_swagger_ui::SwaggerUi;
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "dio",
+        description = "Facts, principles and entries of other kinds. Errors are `application/problem+json` \
+                       bodies whose `code` member names the kind of error."
+    ),
+    paths(
+        route::index,
+        route::get_kinds,
+        route::create_kind,
+        route::list_entries,
+        route::create_entry,
+        route::random_entry,
+        route::get_entry,
+        route::update_entry,
+        route::patch_entry,
+        route::delete_entry,
+        route::get_tags,
+        route::add_tags,
+        route::remove_tag,
+        route::today,
+        route::search,
+        route::import,
+        route::export,
+        route::register_user,
+        route::login,
+        route::me,
+        route::logout,
+        route::get_users,
+        route::delete_user,
+        route::set_user_role,
+        route::get_api_keys,
+        route::create_api_key,
+        route::delete_api_key,
+        health::live,
+        health::ready,
+        metrics::scrape,
+    ),
+    modifiers(&Bearer, &Healthcheck)
+)]
+pub struct ApiDoc;
+
+/// Declares the `bearer` scheme named by routes that need a session token or
+/// an API key.
+struct Bearer;
+
+impl Modify for Bearer {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
+        );
+    }
+}
+
+/// Documents `/healthcheck`, the older name of `/health/ready`.
+struct Healthcheck;
+
+impl Modify for Healthcheck {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let Some(mut item) = openapi.paths.paths.get("/health/ready").cloned() else {
+            return;
+        };
+        if let Some(get) = &mut item.get {
+            get.deprecated = Some(Deprecated::True);
+            get.operation_id = Some("healthcheck".to_owned());
+        }
+        openapi.paths.paths.insert("/healthcheck".to_owned(), item);
+    }
+}
+
+/// Registers `/openapi.json` and the docs page, outside rate limiting and
+/// authentication.
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(SwaggerUi::new("/docs/{_:.*}").url("/openapi.json", ApiDoc::openapi()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ApiDoc;
+    use crate::{
+        auth::AuthSettings,
+        config::{Config, ConfigArgs},
+        db::{kinds::RESERVED, DioStore, Kinds, MemoryStore},
+        health::Readiness,
+        metrics::Metrics,
+        model::Kind,
+        ratelimit::{Budget, RateLimiter},
+        route,
+    };
+    use actix_web::{
+        dev::{ResourceMap, Service},
+        http::{Method, StatusCode},
+        test,
+        web::Data,
+        App,
+    };
+    use std::{
+        cell::RefCell,
+        collections::{BTreeMap, BTreeSet, HashMap},
+        rc::Rc,
+        sync::Arc,
+    };
+    use utoipa::OpenApi;
+
+    const METHODS: [Method; 5] = [
+        Method::GET,
+        Method::POST,
+        Method::PUT,
+        Method::PATCH,
+        Method::DELETE,
+    ];
+
+    /// Served for people and tools reading the document, not part of it.
+    const DOCS: [&str; 2] = ["/docs/{_:.*}", "/openapi.json"];
+
+    /// Every path pattern in `rmap`. actix-web cannot list the routes of an
+    /// app, but the debug output of its resource map holds every pattern.
+    fn patterns(rmap: &ResourceMap) -> BTreeSet<String> {
+        let debug = format!("{rmap:?}");
+        let mut patterns = BTreeSet::new();
+        for def in debug.split("patterns: ").skip(1) {
+            let list = match def.strip_prefix("Single(") {
+                Some(single) => single.split_once(')').unwrap().0,
+                None => {
+                    def.strip_prefix("List([")
+                        .unwrap()
+                        .split_once("])")
+                        .unwrap()
+                        .0
+                }
+            };
+            let quoted = list.split('"').skip(1).step_by(2);
+            patterns.extend(quoted.filter(|path| !path.is_empty()).map(str::to_owned));
+        }
+        patterns
+    }
+
+    /// A path matching `pattern`, with `{kind}` filled in with a served kind
+    /// and every other `{param}` with 1, and the names of the params.
+    fn sample(pattern: &str) -> (String, BTreeSet<String>) {
+        let mut path = String::new();
+        let mut params = BTreeSet::new();
+        for (i, part) in pattern.split(['{', '}']).enumerate() {
+            match i % 2 {
+                0 => path.push_str(part),
+                _ => {
+                    path.push_str(if part == "kind" { "facts" } else { "1" });
+                    params.insert(part.to_owned());
+                }
+            }
+        }
+        (path, params)
+    }
+
+    #[actix_web::test]
+    async fn documents_every_route_of_the_app() {
+        let store: Arc<dyn DioStore> = Arc::new(MemoryStore::default());
+        let args = ConfigArgs {
+            database_url: Some("memory://".to_owned()),
+            ..ConfigArgs::default()
+        };
+        let settings = Config::resolve(&args, &HashMap::new()).unwrap();
+        let kinds = Data::new(Kinds::open(store.clone(), &settings).await.unwrap());
+        let off = Budget {
+            burst: 0,
+            per_second: 0.0,
+        };
+        let auth = AuthSettings {
+            memory_kib: 8,
+            iterations: 1,
+            parallelism: 1,
+            session_ttl: chrono::Duration::hours(1),
+        };
+        let captured: Rc<RefCell<Option<ResourceMap>>> = Rc::default();
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::from(store))
+                .app_data(kinds.clone())
+                .app_data(Data::new(auth))
+                .app_data(Data::new(RateLimiter::new(off, off)))
+                .app_data(Data::new(Metrics::new()))
+                .app_data(Data::new(Readiness::default()))
+                .wrap_fn({
+                    let captured = captured.clone();
+                    move |req, srv| {
+                        let rmap = || req.request().resource_map().clone();
+                        captured.borrow_mut().get_or_insert_with(rmap);
+                        srv.call(req)
+                    }
+                })
+                .configure(|cfg| route::config(cfg, &kinds)),
+        )
+        .await;
+        // The params captured by the route answering `method` on `path`, if
+        // any. A `PUT /facts/random` is answered by `/facts/{id}`, so they
+        // tell routes apart where `match_pattern` cannot.
+        let route_of = |method: Method, path: String| {
+            let req = test::TestRequest::default().method(method).uri(&path);
+            let app = &app;
+            async move {
+                let res = test::call_service(app, req.to_request()).await;
+                let status = res.status();
+                let answered = res.headers().contains_key("content-type");
+                // Unrouted requests get an empty 404, and unknown methods 405.
+                let unrouted = status == StatusCode::METHOD_NOT_ALLOWED
+                    || status == StatusCode::NOT_FOUND && !answered;
+                let params = res.request().match_info().iter();
+                let params: BTreeSet<_> = params.map(|(name, _)| name.to_owned()).collect();
+                Some(params).filter(|_| !unrouted)
+            }
+        };
+
+        let mut documented = BTreeMap::new();
+        for (path, item) in ApiDoc::openapi().paths.paths {
+            let operations = [
+                ("GET", &item.get),
+                ("POST", &item.post),
+                ("PUT", &item.put),
+                ("PATCH", &item.patch),
+                ("DELETE", &item.delete),
+            ];
+            let methods: BTreeSet<_> = (operations.into_iter())
+                .filter(|(_, operation)| operation.is_some())
+                .map(|(method, _)| method)
+                .collect();
+            documented.insert(path, methods);
+        }
+        assert_eq!(
+            route_of(Method::GET, "/".to_owned()).await,
+            Some(BTreeSet::new())
+        );
+        let rmap = captured.borrow().clone().unwrap();
+        let registered = patterns(&rmap);
+        let registered: BTreeSet<_> = (registered.into_iter())
+            .filter(|path| !DOCS.contains(&path.as_str()))
+            .collect();
+        let documented_paths: BTreeSet<_> = documented.keys().cloned().collect();
+        assert_eq!(registered, documented_paths);
+        // `/{kind}` comes last, so a kind named after another route is hidden.
+        let hidden: BTreeSet<_> = (registered.iter())
+            .map(|path| path.trim_start_matches('/').split('/').next().unwrap())
+            .filter(|segment| Kind::new(*segment).is_ok() && !RESERVED.contains(segment))
+            .collect();
+        assert!(
+            hidden.is_empty(),
+            "route segments missing from kinds::RESERVED: {hidden:?}"
+        );
+
+        for (path, methods) in &documented {
+            let (sample, params) = sample(path);
+            assert!(rmap.has_resource(&sample), "{path}");
+            for method in METHODS {
+                let route = route_of(method.clone(), sample.clone()).await;
+                match methods.contains(method.as_str()) {
+                    true => assert_eq!(route.as_ref(), Some(&params), "{method} {path}"),
+                    false => assert_ne!(route.as_ref(), Some(&params), "{method} {path}"),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,274 @@
+//! `ratelimit` throttles clients with token buckets kept in memory.
+//!
+//! Every client has one bucket for reads (`GET` and `HEAD`) and one for
+//! everything else. Clients are told apart by their [`Identity`], or by peer
+//! IP address when they send no valid bearer token. Each request takes a
+//! token; buckets refill continuously up to their burst size.
+//!
+//! Requests are first charged to their peer IP address, before their bearer
+//! token is looked up, so that unknown tokens cannot reach the store without
+//! limit. Once the token resolves, the token goes back to the peer and is
+//! taken from the bucket of the identity instead.
+
+use crate::{auth::Identity, error::DioError};
+use actix_web::{
+    body::EitherBody,
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::{
+        header::{HeaderName, HeaderValue},
+        Method,
+    },
+    web, HttpMessage,
+};
+use futures::future::{ready, LocalBoxFuture, Ready};
+use std::{
+    collections::HashMap,
+    rc::Rc,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Buckets kept before full ones are dropped, which forgets idle clients.
+const SWEEP_AT: usize = 10_000;
+
+/// Size and refill rate of one kind of bucket. A `burst` of 0 turns it off.
+#[derive(Clone, Copy, Debug)]
+pub struct Budget {
+    pub burst: u32,
+    pub per_second: f64,
+}
+
+impl Budget {
+    /// Time to refill `tokens`.
+    fn refill_time(&self, tokens: f64) -> Duration {
+        Duration::from_secs_f64(tokens.max(0.0) / self.per_second)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Kind {
+    Read,
+    Write,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Outcome of [`RateLimiter::take`].
+struct Verdict {
+    allowed: bool,
+    budget: Budget,
+    remaining: u32,
+    /// Until the bucket is full again.
+    reset: Duration,
+    /// Until the next token, when not `allowed`.
+    retry_after: Duration,
+}
+
+/// The buckets of every client, shared by all workers.
+#[derive(Debug)]
+pub struct RateLimiter {
+    read: Budget,
+    write: Budget,
+    buckets: Mutex<HashMap<(String, Kind), Bucket>>,
+}
+
+impl RateLimiter {
+    /// Limits reads to `read` and everything else to `write`.
+    pub fn new(read: Budget, write: Budget) -> Self {
+        Self {
+            read,
+            write,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes a token from the `kind` bucket of `client`, or `None` if that
+    /// budget is turned off.
+    fn take(&self, client: String, kind: Kind, now: Instant) -> Option<Verdict> {
+        let budget = match kind {
+            Kind::Read => self.read,
+            Kind::Write => self.write,
+        };
+        if budget.burst == 0 {
+            return None;
+        }
+        let burst = f64::from(budget.burst);
+
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= SWEEP_AT {
+            let (read, write) = (self.read, self.write);
+            buckets.retain(|(_, kind), bucket| {
+                let budget = if *kind == Kind::Read { read } else { write };
+                let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+                bucket.tokens + elapsed * budget.per_second < f64::from(budget.burst)
+            });
+        }
+        let bucket = buckets.entry((client, kind)).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+        });
+
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * budget.per_second).min(burst);
+        bucket.updated = now;
+        let allowed = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+
+        Some(Verdict {
+            allowed,
+            budget,
+            remaining: bucket.tokens as u32,
+            reset: budget.refill_time(burst - bucket.tokens),
+            retry_after: budget.refill_time(1.0 - bucket.tokens),
+        })
+    }
+
+    /// Gives back a token taken from the `kind` bucket of `client`.
+    fn refund(&self, client: &str, kind: Kind) {
+        let burst = match kind {
+            Kind::Read => self.read.burst,
+            Kind::Write => self.write.burst,
+        };
+        let mut buckets = self.buckets.lock().unwrap();
+        if let Some(bucket) = buckets.get_mut(&(client.to_owned(), kind)) {
+            bucket.tokens = (bucket.tokens + 1.0).min(f64::from(burst));
+        }
+    }
+}
+
+/// Middleware that answers `429 Too Many Requests` once a client runs out of
+/// tokens, and sets `RateLimit-Limit`, `RateLimit-Remaining` and
+/// `RateLimit-Reset` on every response. Reads the [`RateLimiter`] registered
+/// as app data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RateLimit {
+    /// Charges the peer IP address. Wraps `auth::Authenticate`.
+    Peer,
+    /// Moves the charge of signed-in clients from their peer IP address to
+    /// their identity. Runs after `auth::Authenticate`.
+    Identity,
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Transform = RateLimitMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimitMiddleware {
+            service: Rc::new(service),
+            by: *self,
+        }))
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    service: Rc<S>,
+    by: RateLimit,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let by = self.by;
+        Box::pin(async move {
+            let limiter = (req.app_data::<web::Data<RateLimiter>>().cloned())
+                .expect("a RateLimiter is registered as app data");
+            let kind = match *req.method() {
+                Method::GET | Method::HEAD => Kind::Read,
+                _ => Kind::Write,
+            };
+            let peer = (req.peer_addr()).map_or_else(String::new, |addr| addr.ip().to_string());
+            let client = match by {
+                RateLimit::Peer => peer,
+                RateLimit::Identity => {
+                    let identity = req.extensions().get::<Identity>().map(Identity::subject);
+                    // Anonymous requests stay charged to their peer.
+                    let Some(identity) = identity else {
+                        return Ok(service.call(req).await?.map_into_left_body());
+                    };
+                    limiter.refund(&peer, kind);
+                    identity
+                }
+            };
+
+            let Some(verdict) = limiter.take(client, kind, Instant::now()) else {
+                return Ok(service.call(req).await?.map_into_left_body());
+            };
+            let mut res = if verdict.allowed {
+                service.call(req).await?.map_into_left_body()
+            } else {
+                let retry_after = ceil_secs(verdict.retry_after);
+                (req.error_response(DioError::RateLimited { retry_after })).map_into_right_body()
+            };
+
+            let headers = res.headers_mut();
+            // Set by the identity's verdict, which wins over the peer's.
+            if headers.contains_key("ratelimit-limit") {
+                return Ok(res);
+            }
+            for (name, value) in [
+                ("ratelimit-limit", u64::from(verdict.budget.burst)),
+                ("ratelimit-remaining", u64::from(verdict.remaining)),
+                ("ratelimit-reset", ceil_secs(verdict.reset)),
+            ] {
+                headers.insert(HeaderName::from_static(name), HeaderValue::from(value));
+            }
+            Ok(res)
+        })
+    }
+}
+
+fn ceil_secs(duration: Duration) -> u64 {
+    duration.as_secs_f64().ceil() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn takes_refills_and_refunds() {
+        let budget = Budget {
+            burst: 2,
+            per_second: 1.0,
+        };
+        let limiter = RateLimiter::new(budget, budget);
+        let now = Instant::now();
+        let take = |kind, at| limiter.take("10.0.0.1".to_owned(), kind, at).unwrap();
+
+        assert!(take(Kind::Write, now).allowed);
+        assert!(take(Kind::Write, now).allowed);
+        let denied = take(Kind::Write, now);
+        assert!(!denied.allowed);
+        assert_eq!(denied.retry_after, Duration::from_secs(1));
+        assert!(take(Kind::Read, now).allowed);
+
+        limiter.refund("10.0.0.1", Kind::Write);
+        assert!(take(Kind::Write, now).allowed);
+        assert!(!take(Kind::Write, now).allowed);
+        assert!(take(Kind::Write, now + Duration::from_secs(1)).allowed);
+    }
+}
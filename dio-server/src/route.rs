@@ -1,143 +1,2307 @@
 //! `actors` acts as the routes controller for REST Api.
 //!
-//! TODO: Add [`services`]() to implementDB logic like `find_one` ...
+//! Handlers only talk to the [`DioStore`] and [`Kinds`] registered as app
+//! data, never to a database driver directly.
 //!
 //! See https://github.com/actix/examples/blob/master/databases/mongodb/src/main.rs
 
-use crate::model::{Facts, Principles};
-use actix_web::{get, post, web, HttpResponse, Responder};
-use dio_server::{COLL_NAME_FACTS, COLL_NAME_PRINCIPLES, DB_NAME};
-use futures::stream::TryStreamExt;
-use mongodb::{bson::doc, options::FindOptions, Client, Collection};
+use crate::{
+    auth::{self, AuthSettings, Authenticate, Require, SignedIn},
+    bulk::{self, Format, RowError, NDJSON},
+    daily,
+    db::{Conflict, DioStore, Kinds, ListQuery, Repo, SortKey},
+    error::DioError,
+    health,
+    metrics::{self, Handler, Measure},
+    model::{
+        ApiKey, Entry, Kind, KindDef, Page, Record, Role, Scope, SearchHit, Session, TagCount, User,
+    },
+    openapi,
+    ratelimit::RateLimit,
+    search::highlights,
+    telemetry::Trace,
+};
+use actix_web::{
+    get,
+    guard::{self, Guard},
+    http::header,
+    post, web,
+    web::Bytes,
+    HttpMessage, HttpRequest, HttpResponse, Responder,
+};
+use chrono::{DateTime, NaiveDate, Timelike, Utc};
+use dio_core::{normalize_tag, TagFilter, ValidationError};
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{collections::HashSet, sync::Arc};
+use utoipa::{IntoParams, ToSchema};
 
-// -> HttpResponse | impl Responder
-#[get("/facts/{id}")]
-async fn get_fact(client: web::Data<Client>, path: web::Path<String>) -> impl Responder {
-    let id = path.into_inner();
+/// Handlers fail with a [`DioError`], which renders itself as problem JSON.
+type Response = Result<HttpResponse, DioError>;
+
+/// The entries of the kind named in the path. Routes under `/{kind}` are
+/// guarded by [`known_kind`], so this only fails for a kind gone since.
+fn entries_of(kinds: &Kinds, kind: &str) -> Result<Arc<dyn Repo<Entry>>, DioError> {
+    (kinds.get(kind)).ok_or_else(|| DioError::NotFound(format!("No kind named `{kind}`")))
+}
+
+/// The entries of the kind named by a `kind` query parameter.
+fn entries_of_param(kinds: &Kinds, kind: &str) -> Result<Arc<dyn Repo<Entry>>, DioError> {
+    kinds.get(kind).ok_or_else(|| {
+        let known: Vec<_> = kinds.all().into_iter().map(|(kind, _)| kind).collect();
+        DioError::Validation(format!(
+            "Unknown kind `{kind}`. Use one of: {}",
+            known
+                .iter()
+                .map(Kind::as_str)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))
+    })
+}
+
+/// What an entry of `kind` is called in error messages.
+fn noun(kind: &str) -> String {
+    format!("entry of {kind}")
+}
+
+#[utoipa::path(
+    get,
+    path = "/{kind}/{id}",
+    tag = "entries",
+    params(("kind" = String, Path), ("id" = i32, Path)),
+    responses(
+        (status = 200, body = Entry),
+        (status = 404, description = "No such kind, or no entry of it has this id"),
+    )
+)]
+async fn get_entry(kinds: web::Data<Kinds>, path: web::Path<(String, i32)>) -> Response {
+    let (kind, id) = path.into_inner();
+    get_record(entries_of(&kinds, &kind)?.as_ref(), id, &noun(&kind)).await
+}
+
+/// Paginated, see [`ListParams`], or streamed as NDJSON if the client accepts it.
+#[utoipa::path(
+    get,
+    path = "/{kind}",
+    tag = "entries",
+    params(("kind" = String, Path), ListParams),
+    responses(
+        (status = 200, content(
+            (Page<Entry>),
+            (Entry = "application/x-ndjson"),
+        )),
+        (status = 400, description = "Invalid query string"),
+        (status = 404, description = "No such kind"),
+    )
+)]
+async fn list_entries(
+    kinds: web::Data<Kinds>,
+    path: web::Path<String>,
+    params: web::Query<ListParams>,
+    req: HttpRequest,
+) -> Response {
+    let repo = entries_of(&kinds, &path)?;
+    list_records(repo.as_ref(), params.into_inner(), &req).await
+}
+
+/// Assigns the next id unless the body carries one.
+#[utoipa::path(
+    post,
+    path = "/{kind}",
+    tag = "entries",
+    params(("kind" = String, Path)),
+    request_body(content = Entry, description = "Only `title` is required"),
+    responses(
+        (status = 201, body = Entry, headers(("Location" = String))),
+        (status = 400, description = "Invalid entry"),
+        (status = 404, description = "No such kind"),
+        (status = 409, description = "The id is taken"),
+    ),
+    security(("bearer" = []))
+)]
+async fn create_entry(
+    kinds: web::Data<Kinds>,
+    path: web::Path<String>,
+    body: web::Json<Value>,
+) -> Response {
+    let kind = path.into_inner();
+    let repo = entries_of(&kinds, &kind)?;
+    create_record(repo.as_ref(), body.into_inner(), &kind, &noun(&kind)).await
+}
+
+/// Replaces the whole entry. The id in the path wins over any id in the body.
+#[utoipa::path(
+    put,
+    path = "/{kind}/{id}",
+    tag = "entries",
+    params(("kind" = String, Path), ("id" = i32, Path)),
+    request_body = Entry,
+    responses(
+        (status = 200, body = Entry),
+        (status = 400, description = "Invalid entry"),
+        (status = 404, description = "No such kind, or no entry of it has this id"),
+    ),
+    security(("bearer" = []))
+)]
+async fn update_entry(
+    kinds: web::Data<Kinds>,
+    path: web::Path<(String, i32)>,
+    body: web::Json<Value>,
+) -> Response {
+    let (kind, id) = path.into_inner();
+    let repo = entries_of(&kinds, &kind)?;
+    update_record(
+        repo.as_ref(),
+        id,
+        body.into_inner(),
+        false,
+        &kind,
+        &noun(&kind),
+    )
+    .await
+}
+
+/// Merges the given fields into the stored entry.
+#[utoipa::path(
+    patch,
+    path = "/{kind}/{id}",
+    tag = "entries",
+    params(("kind" = String, Path), ("id" = i32, Path)),
+    request_body(content = Object, description = "Fields of an entry"),
+    responses(
+        (status = 200, body = Entry),
+        (status = 400, description = "Invalid entry"),
+        (status = 404, description = "No such kind, or no entry of it has this id"),
+    ),
+    security(("bearer" = []))
+)]
+async fn patch_entry(
+    kinds: web::Data<Kinds>,
+    path: web::Path<(String, i32)>,
+    body: web::Json<Value>,
+) -> Response {
+    let (kind, id) = path.into_inner();
+    let repo = entries_of(&kinds, &kind)?;
+    update_record(
+        repo.as_ref(),
+        id,
+        body.into_inner(),
+        true,
+        &kind,
+        &noun(&kind),
+    )
+    .await
+}
 
-    let collection: Collection<Facts> = client.database(DB_NAME).collection(COLL_NAME_FACTS);
+#[utoipa::path(
+    delete,
+    path = "/{kind}/{id}",
+    tag = "entries",
+    params(("kind" = String, Path), ("id" = i32, Path)),
+    responses(
+        (status = 204, description = "Deleted"),
+        (status = 404, description = "No such kind, or no entry of it has this id"),
+    ),
+    security(("bearer" = []))
+)]
+async fn delete_entry(kinds: web::Data<Kinds>, path: web::Path<(String, i32)>) -> Response {
+    let (kind, id) = path.into_inner();
+    delete_record(entries_of(&kinds, &kind)?.as_ref(), id, &noun(&kind)).await
+}
 
-    let find_one = collection.find_one(doc! {"id": &id}, None).await;
+/// Every tag of the entries of `kind`, with the number of entries carrying it.
+#[utoipa::path(
+    get,
+    path = "/{kind}/tags",
+    tag = "entries",
+    params(("kind" = String, Path)),
+    responses(
+        (status = 200, body = [TagCount]),
+        (status = 404, description = "No such kind"),
+    )
+)]
+async fn get_tags(kinds: web::Data<Kinds>, path: web::Path<String>) -> Response {
+    let repo = entries_of(&kinds, &path)?;
+    Ok(HttpResponse::Ok().json(repo.tags().await?))
+}
 
-    match find_one {
-        Ok(Some(fact)) => HttpResponse::Ok().json(fact), // Ok(None) => HttpResponse::NotFound().finish(),
-        Ok(None) => HttpResponse::NotFound().body(format!("No fact found with id {id}")),
-        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+#[derive(Debug, Deserialize, ToSchema)]
+struct NewTags {
+    /// Lowercased. 1 to 32 letters, digits, `_` or `-` each.
+    #[schema(example = json!(["health"]))]
+    tags: Vec<String>,
+}
+
+/// Adds tags to an entry, on top of those it has.
+#[utoipa::path(
+    post,
+    path = "/{kind}/{id}/tags",
+    tag = "entries",
+    params(("kind" = String, Path), ("id" = i32, Path)),
+    request_body = NewTags,
+    responses(
+        (status = 200, body = Entry),
+        (status = 400, description = "Invalid tag, or too many tags"),
+        (status = 404, description = "No such kind, or no entry of it has this id"),
+    ),
+    security(("bearer" = []))
+)]
+async fn add_tags(
+    kinds: web::Data<Kinds>,
+    path: web::Path<(String, i32)>,
+    body: web::Json<NewTags>,
+) -> Response {
+    let (kind, id) = path.into_inner();
+    let tags = body.into_inner().tags;
+    let repo = entries_of(&kinds, &kind)?;
+    retag(repo.as_ref(), id, &kind, |own| own.extend(tags)).await
+}
+
+/// Removes a tag from an entry. Removing a tag it does not carry changes nothing.
+#[utoipa::path(
+    delete,
+    path = "/{kind}/{id}/tags/{tag}",
+    tag = "entries",
+    params(("kind" = String, Path), ("id" = i32, Path), ("tag" = String, Path)),
+    responses(
+        (status = 200, body = Entry),
+        (status = 404, description = "No such kind, or no entry of it has this id"),
+    ),
+    security(("bearer" = []))
+)]
+async fn remove_tag(kinds: web::Data<Kinds>, path: web::Path<(String, i32, String)>) -> Response {
+    let (kind, id, tag) = path.into_inner();
+    let tag = normalize_tag(&tag);
+    let repo = entries_of(&kinds, &kind)?;
+    retag(repo.as_ref(), id, &kind, |own| {
+        own.retain(|own| *own != tag)
+    })
+    .await
+}
+
+/// Applies `edit` to the tags of entry `id`, and stores the entry if that
+/// changed them.
+async fn retag(
+    repo: &dyn Repo<Entry>,
+    id: i32,
+    kind: &str,
+    edit: impl FnOnce(&mut Vec<String>),
+) -> Response {
+    let noun = noun(kind);
+    let not_found = || DioError::NotFound(format!("No {noun} found with id {id}"));
+    let mut entry = repo.get(id).await?.ok_or_else(not_found)?;
+    let before = entry.tags.clone();
+    edit(&mut entry.tags);
+    (entry.normalize()).map_err(|err| DioError::Validation(format!("Invalid {noun}: {err}")))?;
+    if entry.tags == before {
+        return Ok(HttpResponse::Ok().json(entry));
+    }
+    entry.updated_at = Some(Utc::now().with_nanosecond(0).unwrap());
+    match repo.update(id, entry).await? {
+        Some(updated) => Ok(HttpResponse::Ok().json(updated)),
+        None => Err(not_found()),
     }
 }
 
-#[get("/facts")]
-async fn get_facts(client: web::Data<Client>) -> impl Responder {
-    let collection: Collection<Facts> = client.database(DB_NAME).collection(COLL_NAME_FACTS);
-    let find_all = collection.find(None, None).await;
-    match find_all {
-        Ok(mut stream) => {
-            let mut facts = Vec::new();
-            while let Some(fact) = stream.try_next().await.unwrap() {
-                facts.push(fact);
-            }
-            HttpResponse::Ok().json(facts)
-        }
-        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
-    }
-    // dbg!(&collection);
-    // let find_all = collection.find(None, None).await;
-    // match find_all {
-    //     Ok(facts) => {
-    //         let deserialize_current = facts.deserialize_current();
-    //         let deserialize_current = deserialize_current.unwrap();
-    //         HttpResponse::Ok().json(deserialize_current)
-    //     }
-    //     Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
-    // }
-}
-
-#[post("/principles")]
-async fn create_principle(
-    client: web::Data<Client>, // form: web::Form<Principles>,
-    param_obj: web::Json<Principles>,
-    // TODO: Get body data here.
-) -> impl Responder {
-    let collection = client.database(DB_NAME).collection(COLL_NAME_PRINCIPLES); // let inner = form.into_inner();
-    let inner = Principles {
-        id: param_obj.id.clone(),
-        title: param_obj.title.clone(),
+/// A kind, as listed by `GET /kinds`.
+#[derive(Debug, Serialize, ToSchema)]
+struct KindView {
+    kind: Kind,
+    /// Number of entries of the kind.
+    entries: u64,
+    /// Whether the kind comes from the `kinds` setting, rather than `POST /kinds`.
+    configured: bool,
+}
+
+/// Every kind served under `/{kind}`.
+#[utoipa::path(
+    get,
+    path = "/kinds",
+    tag = "entries",
+    responses((status = 200, body = [KindView]))
+)]
+async fn get_kinds(kinds: web::Data<Kinds>) -> Response {
+    let mut views = Vec::new();
+    for (kind, repo) in kinds.all() {
+        views.push(KindView {
+            entries: repo.count().await?,
+            configured: kinds.is_configured(&kind),
+            kind,
+        });
+    }
+    Ok(HttpResponse::Ok().json(views))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct NewKind {
+    /// 1 to 32 lowercase letters, digits or `_`, starting with a letter.
+    #[schema(value_type = String)]
+    kind: Kind,
+}
+
+/// Registers a kind of entries, served under `/{kind}` from then on, and
+/// after restarts.
+#[utoipa::path(
+    post,
+    path = "/kinds",
+    tag = "entries",
+    request_body = NewKind,
+    responses(
+        (status = 201, body = KindView, headers(("Location" = String))),
+        (status = 400, description = "Invalid name"),
+        (status = 409, description = "The kind exists, or its name or collection is taken"),
+    ),
+    security(("bearer" = []))
+)]
+async fn create_kind(
+    kinds: web::Data<Kinds>,
+    body: web::Json<NewKind>,
+    SignedIn(admin): SignedIn,
+) -> Response {
+    let kind = body.into_inner().kind;
+    kinds
+        .register(KindDef {
+            kind: kind.clone(),
+            created_by: admin.subject(),
+            created_at: Utc::now().with_nanosecond(0).unwrap(),
+        })
+        .await?;
+    Ok(HttpResponse::Created()
+        .insert_header((header::LOCATION, format!("/{kind}")))
+        .json(KindView {
+            kind,
+            entries: 0,
+            configured: false,
+        }))
+}
+
+/// Body of `POST /users` and `POST /login`.
+#[derive(Debug, Deserialize, ToSchema)]
+struct Credentials {
+    username: String,
+    password: String,
+}
+
+/// Registers a reader account. Responds `409 Conflict` if the username is
+/// taken. Admins are created with `dio-server admin`.
+#[utoipa::path(
+    tag = "auth",
+    request_body = Credentials,
+    responses(
+        (status = 201, body = UserView),
+        (status = 400, description = "Invalid username or password"),
+        (status = 409, description = "The username is taken"),
+    )
+)]
+#[post("/users")]
+async fn register_user(
+    store: web::Data<dyn DioStore>,
+    settings: web::Data<AuthSettings>,
+    body: web::Json<Credentials>,
+) -> Response {
+    let Credentials { username, password } = body.into_inner();
+    auth::check_credentials(&username, &password)?;
+
+    let password_hash = web::block(move || auth::hash_password(&password, &settings)).await??;
+    let user = User {
+        username,
+        password_hash,
+        role: Role::Reader,
+        created_at: Utc::now().with_nanosecond(0).unwrap(),
     };
-    let result = collection.insert_one(inner, None).await;
-    match result {
-        Ok(res) => {
-            HttpResponse::Ok().body(format!("Created principle with id: {}", res.inserted_id))
+    store.users().insert(user.clone()).await?;
+    Ok(HttpResponse::Created().json(UserView::from(&user)))
+}
+
+/// Exchanges credentials for a bearer token to send as `Authorization: Bearer <token>`.
+#[utoipa::path(
+    tag = "auth",
+    request_body = Credentials,
+    responses(
+        (status = 200, body = Token),
+        (status = 401, description = "Invalid username or password"),
+    )
+)]
+#[post("/login")]
+async fn login(
+    store: web::Data<dyn DioStore>,
+    settings: web::Data<AuthSettings>,
+    body: web::Json<Credentials>,
+) -> Response {
+    let Credentials { username, password } = body.into_inner();
+    let user = store.users().get(&username).await?;
+    // Unknown usernames still cost one verification.
+    let hash = user.as_ref().map(|user| user.password_hash.clone());
+    let verified = web::block(move || match hash {
+        Some(hash) => auth::verify_password(&hash, &password),
+        None => auth::verify_dummy(&password),
+    });
+    let (true, Some(user)) = (verified.await?, user) else {
+        return Err(DioError::Unauthorized(
+            "Invalid username or password".to_owned(),
+        ));
+    };
+
+    let token = auth::new_token();
+    let session = Session {
+        token_digest: auth::token_digest(&token),
+        username: user.username,
+        expires_at: Utc::now() + settings.session_ttl,
+    };
+    let expires_at = session.expires_at;
+    store.sessions().insert(session).await?;
+    Ok(HttpResponse::Ok().json(Token { token, expires_at }))
+}
+
+/// Answer of `POST /login`.
+#[derive(Debug, Serialize, ToSchema)]
+struct Token {
+    token: String,
+    expires_at: DateTime<Utc>,
+}
+
+/// The user or API key behind the bearer token.
+#[utoipa::path(
+    tag = "auth",
+    responses(
+        (status = 200, body = Object, description = "The user or API key"),
+        (status = 401, description = "Not signed in"),
+    ),
+    security(("bearer" = []))
+)]
+#[get("/me")]
+async fn me(SignedIn(identity): SignedIn) -> impl Responder {
+    HttpResponse::Ok().json(identity)
+}
+
+/// Ends the session of the bearer token.
+#[utoipa::path(
+    tag = "auth",
+    responses(
+        (status = 204, description = "Signed out"),
+        (status = 401, description = "Not signed in"),
+    ),
+    security(("bearer" = []))
+)]
+#[post("/logout")]
+async fn logout(store: web::Data<dyn DioStore>, req: HttpRequest, _user: SignedIn) -> Response {
+    let token = auth::bearer_token(&req).unwrap_or_default();
+    store.sessions().remove(&auth::token_digest(token)).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// What is shown of a user, leaving out the password hash.
+#[derive(Debug, Serialize, ToSchema)]
+struct UserView {
+    username: String,
+    role: Role,
+    created_at: DateTime<Utc>,
+}
+
+impl From<&User> for UserView {
+    fn from(user: &User) -> Self {
+        Self {
+            username: user.username.clone(),
+            role: user.role,
+            created_at: user.created_at,
         }
-        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
     }
 }
 
-#[get("/principles/{id}")]
-async fn get_principle(client: web::Data<Client>, path: web::Path<i32>) -> impl Responder {
+#[utoipa::path(
+    get,
+    path = "/admin/users",
+    tag = "admin",
+    responses((status = 200, body = [UserView])),
+    security(("bearer" = []))
+)]
+async fn get_users(store: web::Data<dyn DioStore>) -> Response {
+    let users = store.users().list().await?;
+    Ok(HttpResponse::Ok().json(users.iter().map(UserView::from).collect::<Vec<_>>()))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct RoleChange {
+    role: Role,
+}
+
+/// Gives a user another role, effective from their next request.
+#[utoipa::path(
+    put,
+    path = "/admin/users/{username}/role",
+    tag = "admin",
+    params(("username" = String, Path)),
+    request_body = RoleChange,
+    responses(
+        (status = 200, body = UserView),
+        (status = 404, description = "No user has this name"),
+    ),
+    security(("bearer" = []))
+)]
+async fn set_user_role(
+    store: web::Data<dyn DioStore>,
+    path: web::Path<String>,
+    body: web::Json<RoleChange>,
+) -> Response {
+    let username = path.into_inner();
+    let not_found = || DioError::NotFound(format!("No user named {username}"));
+    let mut user = store.users().get(&username).await?.ok_or_else(not_found)?;
+    user.role = body.into_inner().role;
+    match store.users().replace(user.clone()).await? {
+        true => Ok(HttpResponse::Ok().json(UserView::from(&user))),
+        false => Err(not_found()),
+    }
+}
+
+/// Deletes an account. Its sessions stop working right away.
+#[utoipa::path(
+    delete,
+    path = "/admin/users/{username}",
+    tag = "admin",
+    params(("username" = String, Path)),
+    responses(
+        (status = 204, description = "Deleted"),
+        (status = 404, description = "No user has this name"),
+    ),
+    security(("bearer" = []))
+)]
+async fn delete_user(store: web::Data<dyn DioStore>, path: web::Path<String>) -> Response {
+    let username = path.into_inner();
+    match store.users().remove(&username).await? {
+        true => Ok(HttpResponse::NoContent().finish()),
+        false => Err(DioError::NotFound(format!("No user named {username}"))),
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct NewApiKey {
+    name: String,
+    scope: Scope,
+}
+
+/// What is shown of an API key. The token is only ever shown when it is minted.
+#[derive(Debug, Serialize, ToSchema)]
+struct ApiKeyView {
+    id: String,
+    name: String,
+    scope: Scope,
+    created_by: String,
+    created_at: DateTime<Utc>,
+}
+
+impl From<&ApiKey> for ApiKeyView {
+    fn from(key: &ApiKey) -> Self {
+        Self {
+            id: key.id.clone(),
+            name: key.name.clone(),
+            scope: key.scope,
+            created_by: key.created_by.clone(),
+            created_at: key.created_at,
+        }
+    }
+}
+
+/// Answer of `POST /admin/api-keys`, the only one carrying the token.
+#[derive(Debug, Serialize, ToSchema)]
+struct NewApiKeyView {
+    #[serde(flatten)]
+    key: ApiKeyView,
+    token: String,
+}
+
+/// Mints an API key. The response is the only time its token is shown.
+#[utoipa::path(
+    post,
+    path = "/admin/api-keys",
+    tag = "admin",
+    request_body = NewApiKey,
+    responses(
+        (status = 201, body = NewApiKeyView),
+        (status = 400, description = "Invalid name"),
+    ),
+    security(("bearer" = []))
+)]
+async fn create_api_key(
+    store: web::Data<dyn DioStore>,
+    body: web::Json<NewApiKey>,
+    SignedIn(admin): SignedIn,
+) -> Response {
+    let NewApiKey { name, scope } = body.into_inner();
+    if !(1..=64).contains(&name.chars().count()) {
+        return Err(DioError::Validation(
+            "API key names are 1 to 64 characters".to_owned(),
+        ));
+    }
+
+    let (id, token) = auth::new_api_key();
+    let key = ApiKey {
+        id,
+        name,
+        scope,
+        token_digest: auth::token_digest(&token),
+        created_by: admin.subject(),
+        created_at: Utc::now().with_nanosecond(0).unwrap(),
+    };
+    store.api_keys().insert(key.clone()).await?;
+    Ok(HttpResponse::Created().json(NewApiKeyView {
+        key: ApiKeyView::from(&key),
+        token,
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/api-keys",
+    tag = "admin",
+    responses((status = 200, body = [ApiKeyView])),
+    security(("bearer" = []))
+)]
+async fn get_api_keys(store: web::Data<dyn DioStore>) -> Response {
+    let keys = store.api_keys().list().await?;
+    Ok(HttpResponse::Ok().json(keys.iter().map(ApiKeyView::from).collect::<Vec<_>>()))
+}
+
+/// Revokes an API key. Requests using it are rejected from then on.
+#[utoipa::path(
+    delete,
+    path = "/admin/api-keys/{id}",
+    tag = "admin",
+    params(("id" = String, Path)),
+    responses(
+        (status = 204, description = "Revoked"),
+        (status = 404, description = "No API key has this id"),
+    ),
+    security(("bearer" = []))
+)]
+async fn delete_api_key(store: web::Data<dyn DioStore>, path: web::Path<String>) -> Response {
     let id = path.into_inner();
-    let collection: Collection<Facts> = client.database(DB_NAME).collection(COLL_NAME_PRINCIPLES);
-    match collection.find_one(doc! {"id": &id}, None).await {
-        Ok(Some(fact)) => HttpResponse::Ok().json(fact), // Ok(None) => HttpResponse::NotFound().finish(),
-        Ok(None) => HttpResponse::NotFound().body(format!("No principle found with id {id}")),
-        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    match store.api_keys().remove(&id).await? {
+        true => Ok(HttpResponse::NoContent().finish()),
+        false => Err(DioError::NotFound(format!("No API key found with id {id}"))),
     }
 }
-/// Results from queries are generally returned via Cursor, a struct which
-/// streams the results back from the server as requested. The Cursor type
-/// implements the Stream trait from the futures crate, and in order to access
-/// its streaming functionality you need to import at least one of the StreamExt
-/// or TryStreamExt traits.
-#[get("/principles")]
-async fn get_principles(client: web::Data<Client>) -> impl Responder {
-    let collection: Collection<Principles> =
-        client.database(DB_NAME).collection(COLL_NAME_PRINCIPLES);
 
-    let find = collection.find(None, None).await;
+/// Query string of list endpoints, e.g. `?limit=20&sort=-created&title=water`.
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ListParams {
+    /// Page size, clamped to 1 to [`MAX_LIMIT`]. Defaults to 50.
+    limit: Option<u64>,
+    /// `next_cursor` of the previous page.
+    cursor: Option<String>,
+    /// Number of records to skip. Ignored when `cursor` is given.
+    offset: Option<u64>,
+    /// `id`, `title` or `created`, prefixed with `-` for descending order.
+    sort: Option<String>,
+    /// Case-insensitive substring of the title.
+    title: Option<String>,
+    /// Tags to filter by, separated by `,` to require all of them, or by `|`
+    /// to require any of them, e.g. `health,sleep` or `health|sleep`.
+    tag: Option<String>,
+    created_after: Option<DateTime<Utc>>,
+    created_before: Option<DateTime<Utc>>,
+}
+
+const MAX_LIMIT: u64 = 500;
 
-    match find {
-        Ok(principles) => {
-            let deserialize_current = principles.deserialize_current();
-            let deserialize_current = deserialize_current.unwrap();
-            dbg!(&deserialize_current);
-            HttpResponse::Ok().json(deserialize_current)
+impl TryFrom<ListParams> for ListQuery {
+    type Error = DioError;
+
+    fn try_from(params: ListParams) -> Result<Self, Self::Error> {
+        let mut query = ListQuery::default();
+        if let Some(limit) = params.limit {
+            query.limit = limit.clamp(1, MAX_LIMIT);
+        }
+        // Cursors are offsets for now, but clients should treat them as opaque.
+        query.offset = match params.cursor {
+            Some(cursor) => {
+                (cursor.parse()).map_err(|_| DioError::Validation("Invalid `cursor`".to_owned()))?
+            }
+            None => params.offset.unwrap_or(0),
+        };
+        if let Some(sort) = params.sort {
+            let (descending, key) = match sort.strip_prefix('-') {
+                Some(key) => (true, key),
+                None => (false, sort.as_str()),
+            };
+            query.descending = descending;
+            query.sort = match key {
+                "id" => SortKey::Id,
+                "title" => SortKey::Title,
+                "created" => SortKey::Created,
+                _ => {
+                    return Err(DioError::Validation(format!(
+                        "Cannot sort by `{key}`. Use id, title or created"
+                    )))
+                }
+            };
+        }
+        if let Some(tag) = params.tag {
+            let filter: TagFilter = (tag.parse())
+                .map_err(|err: ValidationError| DioError::Validation(err.to_string()))?;
+            query.tags = filter.tags;
+            query.any_tag = filter.any;
         }
-        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+        query.title = params.title;
+        query.created_after = params.created_after;
+        query.created_before = params.created_before;
+        Ok(query)
+    }
+}
+
+/// Responds with a [`Page`] of records, or with every matching record as
+/// newline-delimited JSON when the `Accept` header asks for [`NDJSON`].
+async fn list_records<T: Record>(
+    repo: &dyn Repo<T>,
+    params: ListParams,
+    req: &HttpRequest,
+) -> Response {
+    let query = ListQuery::try_from(params)?;
+    if accepts_ndjson(req) {
+        let records = repo.stream(&query).await?;
+        return bulk::respond(HttpResponse::Ok(), Format::Ndjson, records).await;
     }
+    let (items, total) = repo.list(&query).await?;
+    let end = query.offset + items.len() as u64;
+    let next_cursor = (end < total).then(|| end.to_string());
+    Ok(HttpResponse::Ok().json(Page {
+        items,
+        next_cursor,
+        total,
+    }))
 }
 
-pub fn config(cfg: &mut web::ServiceConfig) {
-    cfg.service(index)
-        .service(healthcheck)
-        .service(get_fact)
-        .service(get_facts)
-        .service(get_principle)
-        .service(get_principles)
-        .service(create_principle)
-        .service(echo) // TODO: Remove
-        .route("/hey", web::get().to(manual_hello)); // TODO: Remove
+fn accepts_ndjson(req: &HttpRequest) -> bool {
+    req.headers()
+        .get_all(header::ACCEPT)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|media| media.split(';').next().unwrap_or_default().trim() == NDJSON)
 }
 
-// client
-//     .database("dio")
-//     .collection("facts")
-//     .find_one(doc! {"_id": id.into_inner()}, None),
+/// Registered ahead of `/{kind}/{id}`, which would otherwise reject `random` as an id.
+#[utoipa::path(
+    get,
+    path = "/{kind}/random",
+    tag = "entries",
+    params(("kind" = String, Path)),
+    responses(
+        (status = 200, body = Entry),
+        (status = 404, description = "No such kind, or no entry of it yet"),
+    )
+)]
+async fn random_entry(kinds: web::Data<Kinds>, path: web::Path<String>) -> Response {
+    let kind = path.into_inner();
+    random_record(entries_of(&kinds, &kind)?.as_ref(), &noun(&kind)).await
+}
+
+async fn random_record<T: Record>(repo: &dyn Repo<T>, noun: &str) -> Response {
+    let ids = repo.ids().await?;
+    let Some(&id) = ids.choose(&mut rand::thread_rng()) else {
+        return Err(DioError::NotFound(format!("There is no {noun} yet")));
+    };
+    // Not found if deleted since listing the ids.
+    get_record(repo, id, noun).await
+}
+
+/// Query string of `GET /today`.
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct TodayParams {
+    /// A kind listed by `GET /kinds`.
+    kind: String,
+    /// Clients sharing a seed share a sequence. Defaults to the empty seed.
+    #[serde(default)]
+    seed: String,
+    /// Defaults to the current date in UTC.
+    date: Option<NaiveDate>,
+}
+
+/// The entry of the day, see [`daily`].
+#[utoipa::path(
+    tag = "entries",
+    params(TodayParams),
+    responses(
+        (status = 200, body = Entry),
+        (status = 400, description = "Unknown kind"),
+        (status = 404, description = "There is no entry of this kind yet"),
+    )
+)]
+#[get("/today")]
+async fn today(kinds: web::Data<Kinds>, params: web::Query<TodayParams>) -> Response {
+    let params = params.into_inner();
+    let date = params.date.unwrap_or_else(|| Utc::now().date_naive());
+    let repo = entries_of_param(&kinds, &params.kind)?;
+    record_of_day(repo.as_ref(), &noun(&params.kind), date, &params.seed).await
+}
+
+async fn record_of_day<T: Record>(
+    repo: &dyn Repo<T>,
+    noun: &str,
+    date: NaiveDate,
+    seed: &str,
+) -> Response {
+    let ids = repo.ids().await?;
+    let Some(id) = daily::pick(&ids, date, seed) else {
+        return Err(DioError::NotFound(format!("There is no {noun} yet")));
+    };
+    get_record(repo, id, noun).await
+}
+
+/// Query string of `GET /search`.
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct SearchParams {
+    q: String,
+    /// A kind listed by `GET /kinds`. Searches every kind when omitted.
+    kind: Option<String>,
+    /// Defaults to 20, at most 500.
+    limit: Option<usize>,
+}
+
+/// Ranks entries whose titles contain words of `q`.
+#[utoipa::path(
+    tag = "entries",
+    params(SearchParams),
+    responses(
+        (status = 200, body = [SearchHit]),
+        (status = 400, description = "Unknown kind"),
+    )
+)]
+#[get("/search")]
+async fn search(kinds: web::Data<Kinds>, params: web::Query<SearchParams>) -> Response {
+    let params = params.into_inner();
+    let limit = params.limit.unwrap_or(20).clamp(1, MAX_LIMIT as usize);
+    let mut searched = kinds.all();
+    if let Some(kind) = &params.kind {
+        entries_of_param(&kinds, kind)?;
+        searched.retain(|(known, _)| known.as_str() == kind);
+    }
+
+    let mut hits = Vec::new();
+    for (kind, repo) in searched {
+        hits.extend(search_records(repo.as_ref(), &kind, &params.q, limit).await?);
+    }
+    hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+    hits.truncate(limit);
+    Ok(HttpResponse::Ok().json(hits))
+}
+
+async fn search_records<T: Record>(
+    repo: &dyn Repo<T>,
+    kind: &Kind,
+    query: &str,
+    limit: usize,
+) -> anyhow::Result<Vec<SearchHit>> {
+    let mut hits = Vec::new();
+    for (item, score) in repo.search(query, limit).await? {
+        hits.push(SearchHit {
+            kind: kind.clone(),
+            score,
+            matches: highlights(item.title(), query),
+            item: serde_json::to_value(item)?,
+        });
+    }
+    Ok(hits)
+}
+
+/// How `POST /import` treats records whose id is already stored.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+enum ImportMode {
+    /// Rejects the import.
+    #[default]
+    Reject,
+    /// Replaces the stored records.
+    Upsert,
+}
+
+/// Query string of `POST /import`.
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ImportParams {
+    /// A kind listed by `GET /kinds`.
+    kind: String,
+    /// Defaults to `reject`.
+    #[serde(default)]
+    #[param(inline)]
+    mode: ImportMode,
+    /// Only validates the file and counts what would change.
+    #[serde(default)]
+    dry_run: bool,
+}
+
+/// Largest file `POST /import` accepts.
+const MAX_IMPORT_BYTES: usize = 16 * 1024 * 1024;
+
+/// Row errors listed in the answer to an invalid import. The rest are counted.
+const MAX_ROW_ERRORS: usize = 100;
+
+/// Answer of `POST /import`.
+#[derive(Debug, Serialize, ToSchema)]
+struct ImportReport {
+    /// Records in the file.
+    rows: usize,
+    created: u64,
+    replaced: u64,
+    /// Whether the file was only validated, and the counts are what would change.
+    dry_run: bool,
+    /// Whether the records were written in one transaction. Only `false` on
+    /// MongoDB servers without transactions, and left out on dry runs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    atomic: Option<bool>,
+}
+
+/// Creates records from a JSON array, NDJSON or CSV file, by `Content-Type`.
+/// Rows are validated like the body of a create, and nothing is written
+/// unless every row is valid.
+#[utoipa::path(
+    post,
+    path = "/import",
+    tag = "entries",
+    params(ImportParams),
+    request_body(content(
+        (Vec<Object> = "application/json"),
+        (Object = "application/x-ndjson"),
+        (String = "text/csv"),
+    )),
+    responses(
+        (status = 200, body = ImportReport),
+        (status = 400, description = "Unknown kind, unsupported Content-Type or unreadable file"),
+        (status = 409, description = "An id was taken while importing"),
+        (status = 422, description = "Invalid rows, listed in the `errors` of the problem"),
+    ),
+    security(("bearer" = []))
+)]
+async fn import(
+    kinds: web::Data<Kinds>,
+    params: web::Query<ImportParams>,
+    req: HttpRequest,
+    body: Bytes,
+) -> Response {
+    let params = params.into_inner();
+    let format = Format::from_media_type(req.content_type())?;
+    let repo = entries_of_param(&kinds, &params.kind)?;
+    let noun = noun(&params.kind);
+    let report = import_records(repo.as_ref(), &params.kind, &noun, format, &body, &params).await?;
+    Ok(HttpResponse::Ok().json(report))
+}
+
+async fn import_records<T: Record>(
+    repo: &dyn Repo<T>,
+    kind: &str,
+    noun: &str,
+    format: Format,
+    body: &[u8],
+    params: &ImportParams,
+) -> Result<ImportReport, DioError> {
+    let upsert = params.mode == ImportMode::Upsert;
+    let stored: HashSet<i32> = repo.ids().await?.into_iter().collect();
+    let mut explicit_ids = HashSet::new();
+    let mut items = Vec::new();
+    let mut errors = Vec::new();
+    let mut replaced = 0;
+
+    for (i, row) in bulk::parse(format, body)?.into_iter().enumerate() {
+        let record =
+            row.and_then(|row| new_record::<T>(row, kind, noun).map_err(|e| e.to_string()));
+        let error = match record {
+            Err(error) => error,
+            Ok((_, Some(id))) if explicit_ids.contains(&id) => {
+                format!("Id {id} is given to more than one row")
+            }
+            Ok((_, Some(id))) if stored.contains(&id) && !upsert => Conflict::id(id).to_string(),
+            Ok((item, id)) => {
+                if let Some(id) = id {
+                    explicit_ids.insert(id);
+                    replaced += u64::from(stored.contains(&id));
+                }
+                items.push((item, id));
+                continue;
+            }
+        };
+        errors.push(RowError { row: i + 1, error });
+    }
+    if !errors.is_empty() {
+        let invalid = errors.len();
+        errors.truncate(MAX_ROW_ERRORS);
+        return Err(DioError::InvalidRows { invalid, errors });
+    }
+
+    let rows = items.len();
+    if params.dry_run {
+        return Ok(ImportReport {
+            rows,
+            created: rows as u64 - replaced,
+            replaced,
+            dry_run: true,
+            atomic: None,
+        });
+    }
+    let mut records = Vec::with_capacity(rows);
+    for (mut item, id) in items {
+        let id = match id {
+            // Replaced records keep their `created_at`, as with `PUT`.
+            Some(id) if stored.contains(&id) => {
+                let created_at = repo.get(id).await?.and_then(|current| current.created_at());
+                if let Some(created_at) = created_at {
+                    item.set_created_at(created_at);
+                }
+                id
+            }
+            Some(id) => id,
+            // Explicit ids of the file are not stored yet, so may be handed out.
+            None => loop {
+                let id = repo.next_id().await?;
+                if !explicit_ids.contains(&id) {
+                    break id;
+                }
+            },
+        };
+        item.set_id(id);
+        records.push(item);
+    }
+    let imported = repo.import(records, upsert).await?;
+    Ok(ImportReport {
+        rows,
+        created: imported.created,
+        replaced: imported.replaced,
+        dry_run: false,
+        atomic: Some(imported.atomic),
+    })
+}
+
+/// Query string of `GET /export`.
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ExportParams {
+    /// A kind listed by `GET /kinds`.
+    kind: String,
+    /// Defaults to `json`.
+    #[serde(default)]
+    #[param(inline)]
+    format: Format,
+}
+
+/// Every record of a kind, as a file to download that `POST /import` reads back.
+#[utoipa::path(
+    tag = "entries",
+    params(ExportParams),
+    responses(
+        (status = 200, content(
+            (Vec<Object> = "application/json"),
+            (Object = "application/x-ndjson"),
+            (String = "text/csv"),
+        )),
+        (status = 400, description = "Unknown kind or format"),
+    )
+)]
+#[get("/export")]
+async fn export(kinds: web::Data<Kinds>, params: web::Query<ExportParams>) -> Response {
+    let ExportParams { kind, format } = params.into_inner();
+    export_records(entries_of_param(&kinds, &kind)?.as_ref(), &kind, format).await
+}
+
+async fn export_records<T: Record>(repo: &dyn Repo<T>, kind: &str, format: Format) -> Response {
+    let records = repo.stream(&ListQuery::default()).await?;
+    let attachment = header::ContentDisposition {
+        disposition: header::DispositionType::Attachment,
+        parameters: vec![header::DispositionParam::Filename(format!(
+            "{kind}.{}",
+            format.extension()
+        ))],
+    };
+    let mut res = HttpResponse::Ok();
+    res.insert_header(attachment);
+    bulk::respond(res, format, records).await
+}
+
+/// Responds `200 OK` with the record, or `404 Not Found`.
+async fn get_record<T: Record>(repo: &dyn Repo<T>, id: i32, noun: &str) -> Response {
+    match repo.get(id).await? {
+        Some(item) => Ok(HttpResponse::Ok().json(item)),
+        None => Err(DioError::NotFound(format!("No {noun} found with id {id}"))),
+    }
+}
+
+fn expected_object() -> DioError {
+    DioError::Validation("Expected a JSON object".to_owned())
+}
+
+/// Responds `201 Created` with the stored record, or `409 Conflict` if an
+/// explicit id is already taken.
+async fn create_record<T: Record>(
+    repo: &dyn Repo<T>,
+    body: Value,
+    kind: &str,
+    noun: &str,
+) -> Response {
+    let (mut item, explicit_id) = new_record::<T>(body, kind, noun)?;
+    match explicit_id {
+        Some(id) => item.set_id(id),
+        None => item.set_id(repo.next_id().await?),
+    }
+
+    let created = repo.create(item).await?;
+    Ok(HttpResponse::Created()
+        .insert_header((header::LOCATION, format!("/{kind}/{}", created.id())))
+        .json(created))
+}
+
+/// Validates `body` as a new record of `kind`, with `created_at` defaulting to
+/// now. Returns the id it asks for, if any. The id of the record is 0 until set.
+fn new_record<T: Record>(
+    body: Value,
+    kind: &str,
+    noun: &str,
+) -> Result<(T, Option<i32>), DioError> {
+    let Value::Object(mut doc) = body else {
+        return Err(expected_object());
+    };
+    let explicit_id = match doc.get("id") {
+        None | Some(Value::Null) => None,
+        Some(id) => match id.as_i64().and_then(|id| i32::try_from(id).ok()) {
+            Some(id) if id > 0 => Some(id),
+            _ => {
+                return Err(DioError::Validation(
+                    "`id` must be a positive integer".to_owned(),
+                ))
+            }
+        },
+    };
+    doc.insert("id".to_owned(), 0.into());
+    doc.insert("kind".to_owned(), kind.into());
+
+    let mut item: T = serde_json::from_value(Value::Object(doc))
+        .map_err(|err| DioError::Invalid(format!("Invalid {noun}: {err}")))?;
+    (item.normalize()).map_err(|err| DioError::Invalid(format!("Invalid {noun}: {err}")))?;
+    if item.created_at().is_none() {
+        item.set_created_at(Utc::now().with_nanosecond(0).unwrap());
+    }
+    Ok((item, explicit_id))
+}
+
+/// Shared by `PUT` and `PATCH`. With `merge`, top-level fields of `body` are
+/// laid over the stored record, otherwise `body` replaces it. Either way the
+/// stored `created_at` is kept and `updated_at` is set to now. Bodies that are
+/// not a valid record get `422 Unprocessable Entity`.
+async fn update_record<T: Record>(
+    repo: &dyn Repo<T>,
+    id: i32,
+    body: Value,
+    merge: bool,
+    kind: &str,
+    noun: &str,
+) -> Response {
+    let Value::Object(mut fields) = body else {
+        return Err(expected_object());
+    };
+    let current = (repo.get(id).await?)
+        .ok_or_else(|| DioError::NotFound(format!("No {noun} found with id {id}")))?;
+    let created_at = current.created_at();
+    let mut doc = serde_json::Map::new();
+    if merge {
+        match serde_json::to_value(current).map_err(anyhow::Error::from)? {
+            Value::Object(current) => doc = current,
+            _ => unreachable!("records serialize to JSON objects"),
+        }
+    }
+    fields.remove("created_at");
+    doc.extend(fields);
+    doc.insert("id".to_owned(), id.into());
+    doc.insert("kind".to_owned(), kind.into());
+
+    let mut item: T = serde_json::from_value(Value::Object(doc))
+        .map_err(|err| DioError::Invalid(format!("Invalid {noun}: {err}")))?;
+    (item.normalize()).map_err(|err| DioError::Invalid(format!("Invalid {noun}: {err}")))?;
+    if let Some(created_at) = created_at {
+        item.set_created_at(created_at);
+    }
+    item.set_updated_at(Utc::now().with_nanosecond(0).unwrap());
+    repo.update(id, item)
+        .await?
+        .map(|updated| HttpResponse::Ok().json(updated))
+        .ok_or_else(|| DioError::NotFound(format!("No {noun} found with id {id}")))
+}
+
+/// Responds `204 No Content`, or `404 Not Found` if nothing was deleted.
+async fn delete_record<T: Record>(repo: &dyn Repo<T>, id: i32, noun: &str) -> Response {
+    match repo.delete(id).await? {
+        true => Ok(HttpResponse::NoContent().finish()),
+        false => Err(DioError::NotFound(format!("No {noun} found with id {id}"))),
+    }
+}
+
+/// A route answered by `$handler` and named after it for metrics, restricted
+/// to `$role` and above if given.
+macro_rules! to {
+    ($method:ident, $handler:ident) => {
+        web::$method()
+            .to($handler)
+            .wrap(Handler(stringify!($handler)))
+    };
+    ($method:ident, $handler:ident, $role:expr) => {
+        web::$method()
+            .to($handler)
+            .wrap(Require($role))
+            .wrap(Handler(stringify!($handler)))
+    };
+}
+
+/// Registers every service behind the [`Authenticate`] and [`RateLimit`]
+/// middleware. Routes that need more than anonymous access are wrapped in
+/// [`Require`] with the least role allowed to use them.
+pub fn config(cfg: &mut web::ServiceConfig, kinds: &web::Data<Kinds>) {
+    cfg.configure(health::config)
+        .configure(metrics::config)
+        .configure(openapi::config)
+        .service(
+            web::scope("")
+                .wrap(RateLimit::Identity)
+                .wrap(Authenticate)
+                .wrap(RateLimit::Peer)
+                .wrap(Measure)
+                .wrap(Trace)
+                .app_data(web::PathConfig::default().error_handler(|err, _req| {
+                    DioError::Validation(format!("Invalid path: {err}. Ids are 32-bit integers."))
+                        .into()
+                }))
+                .app_data(web::QueryConfig::default().error_handler(|err, _req| {
+                    DioError::Validation(format!("Invalid query string: {err}")).into()
+                }))
+                .app_data(web::JsonConfig::default().error_handler(|err, _req| {
+                    DioError::Validation(format!("Invalid JSON body: {err}")).into()
+                }))
+                .service(index)
+                .service(today)
+                .service(
+                    web::resource("/kinds")
+                        .route(to!(get, get_kinds))
+                        .route(to!(post, create_kind, Role::Admin)),
+                )
+                .service(search)
+                .service(export)
+                .service(
+                    web::resource("/import")
+                        .app_data(web::PayloadConfig::new(MAX_IMPORT_BYTES))
+                        .route(to!(post, import, Role::Editor)),
+                )
+                .service(register_user)
+                .service(login)
+                .service(logout)
+                .service(me)
+                .service(web::resource("/admin/users").route(to!(get, get_users, Role::Admin)))
+                .service(web::resource("/admin/users/{username}").route(to!(
+                    delete,
+                    delete_user,
+                    Role::Admin
+                )))
+                .service(web::resource("/admin/users/{username}/role").route(to!(
+                    put,
+                    set_user_role,
+                    Role::Admin
+                )))
+                .service(
+                    web::resource("/admin/api-keys")
+                        .route(to!(get, get_api_keys, Role::Admin))
+                        .route(to!(post, create_api_key, Role::Admin)),
+                )
+                .service(web::resource("/admin/api-keys/{id}").route(to!(
+                    delete,
+                    delete_api_key,
+                    Role::Admin
+                )))
+                // Last, so that the routes above win over a kind of the same name.
+                .service(
+                    web::resource("/{kind}/random")
+                        .guard(known_kind(kinds))
+                        .route(to!(get, random_entry)),
+                )
+                .service(
+                    web::resource("/{kind}/tags")
+                        .guard(known_kind(kinds))
+                        .route(to!(get, get_tags)),
+                )
+                .service(
+                    web::resource("/{kind}")
+                        .guard(known_kind(kinds))
+                        .route(to!(get, list_entries))
+                        .route(to!(post, create_entry, Role::Editor)),
+                )
+                .service(
+                    web::resource("/{kind}/{id}")
+                        .guard(known_kind(kinds))
+                        .route(to!(get, get_entry))
+                        .route(to!(put, update_entry, Role::Editor))
+                        .route(to!(patch, patch_entry, Role::Editor))
+                        .route(to!(delete, delete_entry, Role::Admin)),
+                )
+                .service(
+                    web::resource("/{kind}/{id}/tags")
+                        .guard(known_kind(kinds))
+                        .route(to!(post, add_tags, Role::Editor)),
+                )
+                .service(
+                    web::resource("/{kind}/{id}/tags/{tag}")
+                        .guard(known_kind(kinds))
+                        .route(to!(delete, remove_tag, Role::Editor)),
+                ),
+        );
+}
+
+/// Matches paths whose first segment is a kind the server knows, so that any
+/// other path stays a plain 404.
+fn known_kind(kinds: &web::Data<Kinds>) -> impl Guard {
+    let kinds = kinds.clone();
+    guard::fn_guard(move |ctx| {
+        let path = ctx.head().uri.path();
+        let kind = path
+            .trim_start_matches('/')
+            .split('/')
+            .next()
+            .unwrap_or_default();
+        kinds.get(kind).is_some()
+    })
+}
 
 // TODO: Route index to repository.
+#[utoipa::path(
+    tag = "meta",
+    responses((status = 200, body = String))
+)]
 #[get("/")]
 async fn index() -> impl Responder {
     HttpResponse::Ok().body("Hello World! Go to the GitHub repository.")
 }
 
-#[get("/healthcheck")]
-async fn healthcheck() -> impl Responder {
-    HttpResponse::Ok().json("Ok")
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{Config, ConfigArgs},
+        db::MemoryStore,
+        health::Readiness,
+        metrics::Metrics,
+        model::Kind,
+        ratelimit::{Budget, RateLimiter},
+    };
+    use actix_web::{
+        body::MessageBody,
+        dev::{Service, ServiceResponse},
+        http::StatusCode,
+        test,
+        web::Data,
+        App,
+    };
+    use chrono::TimeZone;
+    use serde_json::json;
+    use std::{collections::HashMap, sync::Arc};
 
-#[post("/echo")]
-async fn echo(req_body: String) -> impl Responder {
-    HttpResponse::Ok().body(req_body)
-}
+    /// Opens the kinds served by default, facts and principles, in `store`.
+    async fn kinds_of(store: &Arc<dyn DioStore>) -> Data<Kinds> {
+        let args = ConfigArgs {
+            database_url: Some("memory://".to_owned()),
+            ..ConfigArgs::default()
+        };
+        let settings = Config::resolve(&args, &HashMap::new()).unwrap();
+        Data::new(Kinds::open(store.clone(), &settings).await.unwrap())
+    }
+
+    fn fact(id: i32, text: &str) -> Entry {
+        Entry::new(Kind::new("facts").unwrap(), id, text).unwrap()
+    }
+
+    /// Registers `store`, `kinds`, unlimited rate budgets and the routes.
+    fn with_store(
+        store: Arc<dyn DioStore>,
+        kinds: Data<Kinds>,
+    ) -> impl FnOnce(&mut web::ServiceConfig) {
+        let off = Budget {
+            burst: 0,
+            per_second: 0.0,
+        };
+        with_budget(store, kinds, off)
+    }
+
+    /// Registers `store`, `kinds`, `budget` for both reads and writes, metrics
+    /// and the routes.
+    fn with_budget(
+        store: Arc<dyn DioStore>,
+        kinds: Data<Kinds>,
+        budget: Budget,
+    ) -> impl FnOnce(&mut web::ServiceConfig) {
+        move |cfg| {
+            cfg.app_data(Data::from(store))
+                .app_data(kinds.clone())
+                .app_data(Data::new(RateLimiter::new(budget, budget)))
+                .app_data(Data::new(Metrics::new()))
+                .configure(|cfg| config(cfg, &kinds));
+        }
+    }
+
+    /// Sends `req` and returns its status with the body as JSON, or `Null`.
+    async fn send<S, R, B>(app: &S, req: R) -> (StatusCode, Value)
+    where
+        S: Service<R, Response = ServiceResponse<B>, Error = actix_web::Error>,
+        B: MessageBody,
+    {
+        let res = test::call_service(app, req).await;
+        let status = res.status();
+        let body = test::read_body(res).await;
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    /// Creates `username` with `role` and opens a session for them straight in
+    /// `store`, returning the header that carries it.
+    async fn sign_in(
+        store: &dyn DioStore,
+        username: &str,
+        role: Role,
+    ) -> (header::HeaderName, String) {
+        let user = User {
+            username: username.to_owned(),
+            password_hash: String::new(),
+            role,
+            created_at: Utc::now(),
+        };
+        store.users().insert(user).await.unwrap();
+        let token = auth::new_token();
+        let session = Session {
+            token_digest: auth::token_digest(&token),
+            username: username.to_owned(),
+            expires_at: Utc::now() + chrono::Duration::hours(1),
+        };
+        store.sessions().insert(session).await.unwrap();
+        (header::AUTHORIZATION, format!("Bearer {token}"))
+    }
+
+    #[actix_web::test]
+    async fn answers_crud_with_status_codes() {
+        let store: Arc<dyn DioStore> = Arc::new(MemoryStore::default());
+        let kinds = kinds_of(&store).await;
+        let signed_in = sign_in(&*store, "ada", Role::Admin).await;
+        let app = test::init_service(App::new().configure(with_store(store, kinds))).await;
+
+        let fact = json!({"id": 1, "title": "Cats purr", "created_at": "2024-01-01T00:00:00Z"});
+        let req = test::TestRequest::post().uri("/facts").set_json(&fact);
+        assert_eq!(
+            send(&app, req.to_request()).await.0,
+            StatusCode::UNAUTHORIZED
+        );
+        let req = test::TestRequest::post()
+            .insert_header(signed_in.clone())
+            .uri("/facts")
+            .set_json(&fact);
+        let res = test::call_service(&app, req.to_request()).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(res.headers().get(header::LOCATION).unwrap(), "/facts/1");
+        let mut created: Value = test::read_body_json(res).await;
+        assert_eq!(created["kind"], "facts");
+        created.as_object_mut().unwrap().remove("kind");
+        assert_eq!(created, fact);
+
+        let req = test::TestRequest::post()
+            .insert_header(signed_in.clone())
+            .uri("/facts")
+            .set_json(&fact);
+        assert_eq!(send(&app, req.to_request()).await.0, StatusCode::CONFLICT);
+        let req = test::TestRequest::post()
+            .insert_header(signed_in.clone())
+            .uri("/facts")
+            .set_json(json!({"title": "Dogs bark"}));
+        let (status, created) = send(&app, req.to_request()).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(created["id"], 2);
+        assert!(created["created_at"].is_string());
+        let req = test::TestRequest::post()
+            .insert_header(signed_in.clone())
+            .uri("/facts")
+            .set_json(json!({"id": 2}));
+        assert_eq!(
+            send(&app, req.to_request()).await.0,
+            StatusCode::UNPROCESSABLE_ENTITY
+        );
+        let req = (test::TestRequest::post()
+            .insert_header(signed_in.clone())
+            .uri("/facts"))
+        .insert_header(header::ContentType::json())
+        .set_payload("{");
+        assert_eq!(
+            send(&app, req.to_request()).await.0,
+            StatusCode::BAD_REQUEST
+        );
+
+        let req = test::TestRequest::patch()
+            .insert_header(signed_in.clone())
+            .uri("/facts/1")
+            .set_json(json!({"title": "Cats nap"}));
+        let (status, patched) = send(&app, req.to_request()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(patched["title"], "Cats nap");
+        let req = test::TestRequest::put()
+            .insert_header(signed_in.clone())
+            .uri("/facts/1")
+            .set_json(json!({"title": 7}));
+        assert_eq!(
+            send(&app, req.to_request()).await.0,
+            StatusCode::UNPROCESSABLE_ENTITY
+        );
+        let req = test::TestRequest::put()
+            .insert_header(signed_in.clone())
+            .uri("/facts/3")
+            .set_json(json!({"title": "Birds sing"}));
+        assert_eq!(send(&app, req.to_request()).await.0, StatusCode::NOT_FOUND);
+
+        let req = test::TestRequest::delete()
+            .insert_header(signed_in.clone())
+            .uri("/facts/1");
+        assert_eq!(send(&app, req.to_request()).await.0, StatusCode::NO_CONTENT);
+        let req = test::TestRequest::delete()
+            .insert_header(signed_in.clone())
+            .uri("/facts/1");
+        assert_eq!(send(&app, req.to_request()).await.0, StatusCode::NOT_FOUND);
+        let req = test::TestRequest::get().uri("/facts/1");
+        assert_eq!(send(&app, req.to_request()).await.0, StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn registers_kinds_served_at_their_name() {
+        let store: Arc<dyn DioStore> = Arc::new(MemoryStore::default());
+        let kinds = kinds_of(&store).await;
+        let signed_in = sign_in(&*store, "ada", Role::Admin).await;
+        let app = test::init_service(App::new().configure(with_store(store, kinds))).await;
+        let register = |kind: &str| {
+            let req = test::TestRequest::post()
+                .insert_header(signed_in.clone())
+                .uri("/kinds")
+                .set_json(json!({ "kind": kind }));
+            send(&app, req.to_request())
+        };
+
+        let req = test::TestRequest::get().uri("/quotes");
+        assert_eq!(send(&app, req.to_request()).await.0, StatusCode::NOT_FOUND);
+        let req = test::TestRequest::post()
+            .uri("/kinds")
+            .set_json(json!({"kind": "quotes"}));
+        assert_eq!(
+            send(&app, req.to_request()).await.0,
+            StatusCode::UNAUTHORIZED
+        );
+        let (status, created) = register("quotes").await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(
+            created,
+            json!({"kind": "quotes", "entries": 0, "configured": false})
+        );
+        let (status, problem) = register("quotes").await;
+        assert_eq!(
+            (status, &problem["code"]),
+            (StatusCode::CONFLICT, &json!("conflict"))
+        );
+        // Reserved names would be hidden by the routes they are named after.
+        for reserved in ["search", "kinds", "users"] {
+            let (status, problem) = register(reserved).await;
+            assert_eq!(status, StatusCode::CONFLICT, "{reserved}");
+            assert_eq!(problem["detail"], format!("`{reserved}` is reserved"));
+        }
+        assert_eq!(register("Bad name").await.0, StatusCode::BAD_REQUEST);
+
+        let req = test::TestRequest::post()
+            .insert_header(signed_in.clone())
+            .uri("/quotes")
+            .set_json(json!({"title": "Stay hungry"}));
+        let res = test::call_service(&app, req.to_request()).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(res.headers().get(header::LOCATION).unwrap(), "/quotes/1");
+        let created: Value = test::read_body_json(res).await;
+        assert_eq!(created["kind"], "quotes");
+        let req = test::TestRequest::patch()
+            .insert_header(signed_in.clone())
+            .uri("/quotes/1")
+            .set_json(json!({"title": "Stay foolish"}));
+        let (status, patched) = send(&app, req.to_request()).await;
+        assert_eq!(
+            (status, &patched["title"]),
+            (StatusCode::OK, &json!("Stay foolish"))
+        );
+        for uri in ["/quotes/1", "/quotes/random"] {
+            let (status, quote) = send(&app, test::TestRequest::get().uri(uri).to_request()).await;
+            assert_eq!((status, &quote["id"]), (StatusCode::OK, &json!(1)), "{uri}");
+        }
+        let (status, page) = send(&app, test::TestRequest::get().uri("/quotes").to_request()).await;
+        assert_eq!((status, &page["total"]), (StatusCode::OK, &json!(1)));
+        // Kinds are kept apart.
+        let (_, page) = send(&app, test::TestRequest::get().uri("/facts").to_request()).await;
+        assert_eq!(page["total"], 0);
+
+        let (status, listed) =
+            send(&app, test::TestRequest::get().uri("/kinds").to_request()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            listed,
+            json!([
+                {"kind": "facts", "entries": 0, "configured": true},
+                {"kind": "principles", "entries": 0, "configured": true},
+                {"kind": "quotes", "entries": 1, "configured": false},
+            ])
+        );
+
+        let req = test::TestRequest::delete()
+            .insert_header(signed_in.clone())
+            .uri("/quotes/1");
+        assert_eq!(send(&app, req.to_request()).await.0, StatusCode::NO_CONTENT);
+        let req = test::TestRequest::get().uri("/quotes/1");
+        assert_eq!(send(&app, req.to_request()).await.0, StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn updates_keep_created_at() {
+        let store: Arc<dyn DioStore> = Arc::new(MemoryStore::default());
+        let kinds = kinds_of(&store).await;
+        let signed_in = sign_in(&*store, "ada", Role::Admin).await;
+        let app = test::init_service(App::new().configure(with_store(store, kinds))).await;
+        let created_at = "2024-01-01T00:00:00Z";
+        let req = test::TestRequest::post()
+            .insert_header(signed_in.clone())
+            .uri("/facts")
+            .set_json(json!({"id": 1, "title": "Cats purr", "created_at": created_at}));
+        assert_eq!(send(&app, req.to_request()).await.0, StatusCode::CREATED);
+
+        let req = test::TestRequest::put()
+            .insert_header(signed_in.clone())
+            .uri("/facts/1")
+            .set_json(json!({"title": "Cats nap"}));
+        let (status, put) = send(&app, req.to_request()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(put["created_at"], created_at);
+        let req = test::TestRequest::patch()
+            .insert_header(signed_in.clone())
+            .uri("/facts/1")
+            .set_json(json!({"created_at": "2030-01-01T00:00:00Z"}));
+        let (status, patched) = send(&app, req.to_request()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(patched["title"], "Cats nap");
+        assert_eq!(patched["created_at"], created_at);
+        assert!(patched["updated_at"].is_string());
+    }
+
+    #[actix_web::test]
+    async fn pages_sorts_and_filters_lists() {
+        // One more record than a page can hold, created in reverse id order.
+        let store: Arc<dyn DioStore> = Arc::new(MemoryStore::default());
+        let kinds = kinds_of(&store).await;
+        let epoch = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        for id in 1..=MAX_LIMIT as i32 + 1 {
+            let mut fact = fact(id, &format!("Fact {id:03}"));
+            fact.created_at = Some(epoch - chrono::Duration::minutes(id.into()));
+            kinds.get("facts").unwrap().create(fact).await.unwrap();
+        }
+        let app = test::init_service(App::new().configure(with_store(store, kinds))).await;
+        let list = |query: &str| {
+            let req = test::TestRequest::get().uri(&format!("/facts?{query}"));
+            send(&app, req.to_request())
+        };
+        let ids = |page: &Value| -> Vec<i64> {
+            let items = page["items"].as_array().unwrap();
+            items
+                .iter()
+                .map(|item| item["id"].as_i64().unwrap())
+                .collect()
+        };
+
+        let (status, page) = list("limit=1000").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(ids(&page).len(), MAX_LIMIT as usize);
+        assert_eq!(page["total"], MAX_LIMIT + 1);
+        assert_eq!(page["next_cursor"], MAX_LIMIT.to_string());
+        assert_eq!(ids(&list("limit=0").await.1).len(), 1);
+
+        let mut seen = Vec::new();
+        let mut query = "limit=200".to_owned();
+        loop {
+            let (status, page) = list(&query).await;
+            assert_eq!(status, StatusCode::OK);
+            seen.extend(ids(&page));
+            match page["next_cursor"].as_str() {
+                Some(cursor) => query = format!("limit=200&cursor={cursor}"),
+                None => break,
+            }
+        }
+        assert_eq!(seen, (1..=MAX_LIMIT as i64 + 1).collect::<Vec<_>>());
+
+        assert_eq!(ids(&list("limit=3&sort=-id").await.1), [501, 500, 499]);
+        assert_eq!(ids(&list("limit=3&sort=created").await.1), [501, 500, 499]);
+        assert_eq!(ids(&list("limit=3&sort=-created").await.1), [1, 2, 3]);
+        assert_eq!(ids(&list("limit=2&sort=-title").await.1), [501, 500]);
+        let (_, page) = list("title=Fact%2025&sort=-id").await;
+        assert_eq!(ids(&page)[..3], [259, 258, 257]);
+        assert_eq!(page["total"], 10);
+        let (_, page) = list("created_after=2023-12-31T23:57:30Z").await;
+        assert_eq!(ids(&page), [1, 2]);
+        assert_eq!(list("sort=colour").await.0, StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn filters_and_counts_tags() {
+        let store: Arc<dyn DioStore> = Arc::new(MemoryStore::default());
+        let kinds = kinds_of(&store).await;
+        let signed_in = sign_in(&*store, "ada", Role::Editor).await;
+        for (id, title, tags) in [
+            (1, "Sleep well", ["health", "sleep"].as_slice()),
+            (2, "Eat greens", &["food", "health"]),
+            (3, "Nap after lunch", &["sleep"]),
+        ] {
+            let mut fact = fact(id, title);
+            fact.tags = tags.iter().map(|&tag| tag.to_owned()).collect();
+            kinds.get("facts").unwrap().create(fact).await.unwrap();
+        }
+        let app = test::init_service(App::new().configure(with_store(store, kinds))).await;
+        let get = |uri: &str| send(&app, test::TestRequest::get().uri(uri).to_request());
+        let ids = |page: Value| -> Vec<i64> {
+            let items = page["items"].as_array().unwrap();
+            items
+                .iter()
+                .map(|item| item["id"].as_i64().unwrap())
+                .collect()
+        };
+
+        assert_eq!(ids(get("/facts?tag=health,sleep").await.1), [1]);
+        assert_eq!(ids(get("/facts?tag=health%7Csleep").await.1), [1, 2, 3]);
+        assert_eq!(ids(get("/facts?tag=%20HEALTH").await.1), [1, 2]);
+        let (_, page) = get("/facts?tag=food%7Csleep&sort=-id&limit=1").await;
+        assert_eq!((&page["total"], ids(page.clone())), (&json!(3), vec![3]));
+        assert_eq!(ids(get("/facts?tag=wine").await.1), Vec::<i64>::new());
+        let (status, _) = get("/facts?tag=health,sleep%7Cfood").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            get("/facts?tag=no%20spaces").await.0,
+            StatusCode::BAD_REQUEST
+        );
+
+        let (status, tags) = get("/facts/tags").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            tags,
+            json!([
+                {"tag": "food", "count": 1},
+                {"tag": "health", "count": 2},
+                {"tag": "sleep", "count": 2},
+            ])
+        );
+        assert_eq!(get("/principles/tags").await.1, json!([]));
+        assert_eq!(get("/quotes/tags").await.0, StatusCode::NOT_FOUND);
+
+        let req = test::TestRequest::post()
+            .insert_header(signed_in.clone())
+            .uri("/facts/3/tags")
+            .set_json(json!({"tags": ["Health", "sleep"]}));
+        let (status, tagged) = send(&app, req.to_request()).await;
+        assert_eq!(
+            (status, &tagged["tags"]),
+            (StatusCode::OK, &json!(["health", "sleep"]))
+        );
+        assert!(tagged["updated_at"].is_string());
+        let req = test::TestRequest::delete()
+            .insert_header(signed_in.clone())
+            .uri("/facts/3/tags/SLEEP");
+        let (status, untagged) = send(&app, req.to_request()).await;
+        assert_eq!(
+            (status, &untagged["tags"]),
+            (StatusCode::OK, &json!(["health"]))
+        );
+        let req = test::TestRequest::post()
+            .insert_header(signed_in.clone())
+            .uri("/facts/3/tags")
+            .set_json(json!({"tags": ["no spaces"]}));
+        assert_eq!(
+            send(&app, req.to_request()).await.0,
+            StatusCode::BAD_REQUEST
+        );
+        let req = test::TestRequest::post()
+            .insert_header(signed_in.clone())
+            .uri("/facts/9/tags")
+            .set_json(json!({"tags": ["health"]}));
+        assert_eq!(send(&app, req.to_request()).await.0, StatusCode::NOT_FOUND);
 
-async fn manual_hello() -> impl Responder {
-    HttpResponse::Ok().body("Hey there!")
+        let (_, tags) = get("/facts/tags").await;
+        assert_eq!(tags[1], json!({"tag": "health", "count": 3}));
+        assert_eq!(tags[2], json!({"tag": "sleep", "count": 1}));
+    }
+
+    #[actix_web::test]
+    async fn streams_ndjson_one_record_per_line() {
+        let store: Arc<dyn DioStore> = Arc::new(MemoryStore::default());
+        let kinds = kinds_of(&store).await;
+        for (id, title) in [(1, "Cats purr"), (2, "Dogs bark"), (3, "Birds sing")] {
+            let fact = fact(id, title);
+            kinds.get("facts").unwrap().create(fact).await.unwrap();
+        }
+        let app = test::init_service(App::new().configure(with_store(store, kinds))).await;
+
+        let req = test::TestRequest::get()
+            .uri("/facts?limit=1&sort=-id")
+            .insert_header((
+                header::ACCEPT,
+                "application/json;q=0.5, application/x-ndjson",
+            ));
+        let res = test::call_service(&app, req.to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get(header::CONTENT_TYPE).unwrap(), NDJSON);
+        let body = test::read_body(res).await;
+        let lines: Vec<Value> = (body.split(|byte| *byte == b'\n'))
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).unwrap())
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                json!({"kind": "facts", "id": 3, "title": "Birds sing"}),
+                json!({"kind": "facts", "id": 2, "title": "Dogs bark"}),
+                json!({"kind": "facts", "id": 1, "title": "Cats purr"}),
+            ]
+        );
+        assert!(body.ends_with(b"\n"));
+    }
+
+    #[actix_web::test]
+    async fn imports_rows_all_or_nothing() {
+        let store: Arc<dyn DioStore> = Arc::new(MemoryStore::default());
+        let kinds = kinds_of(&store).await;
+        let signed_in = sign_in(&*store, "ada", Role::Editor).await;
+        let created_at = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let mut fact = fact(1, "Cats purr");
+        fact.created_at = Some(created_at);
+        kinds.get("facts").unwrap().create(fact).await.unwrap();
+        let app = test::init_service(App::new().configure(with_store(store, kinds))).await;
+        let import = |query: &str, content_type: &str, body: &str| {
+            let req = test::TestRequest::post()
+                .insert_header(signed_in.clone())
+                .insert_header((header::CONTENT_TYPE, content_type.to_owned()))
+                .uri(&format!("/import?kind=facts{query}"))
+                .set_payload(body.to_owned());
+            send(&app, req.to_request())
+        };
+        let titles = || async {
+            let req = test::TestRequest::get().uri("/facts");
+            let (_, page) = send(&app, req.to_request()).await;
+            let items = page["items"].as_array().unwrap().clone();
+            items
+                .into_iter()
+                .map(|item| item["title"].clone())
+                .collect::<Vec<_>>()
+        };
+        let rows = "{\"id\": 2, \"title\": \"Dogs bark\"}\n{\"title\": \"Birds sing\"}\n";
+
+        let (status, report) = import("&dry_run=true", NDJSON, rows).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            report,
+            json!({"rows": 2, "created": 2, "replaced": 0, "dry_run": true})
+        );
+        assert_eq!(titles().await, [json!("Cats purr")]);
+
+        // Every bad row is reported, and none of the good ones is stored.
+        let rows =
+            "[{\"title\": \"Owls hoot\"}, {\"title\": 7}, {\"id\": 1, \"title\": \"Cats nap\"}]";
+        let (status, problem) = import("", "application/json", rows).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(problem["code"], "invalid_rows");
+        let rows: Vec<_> = (problem["errors"].as_array().unwrap().iter())
+            .map(|error| error["row"].as_u64().unwrap())
+            .collect();
+        assert_eq!(rows, [2, 3]);
+        assert_eq!(titles().await, [json!("Cats purr")]);
+
+        let rows = "id,title,created_at\n1,Cats nap,2030-01-01T00:00:00Z\n2,Dogs bark,\n";
+        let (status, report) = import("&mode=upsert", "text/csv", rows).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            (&report["created"], &report["replaced"], &report["dry_run"]),
+            (&json!(1), &json!(1), &json!(false))
+        );
+        assert_eq!(titles().await, [json!("Cats nap"), json!("Dogs bark")]);
+        let req = test::TestRequest::get().uri("/facts/1");
+        let (_, replaced) = send(&app, req.to_request()).await;
+        assert_eq!(replaced["created_at"], "2024-01-01T00:00:00Z");
+    }
+
+    #[actix_web::test]
+    async fn searches_both_kinds_by_score() {
+        let store: Arc<dyn DioStore> = Arc::new(MemoryStore::default());
+        let kinds = kinds_of(&store).await;
+        for (id, title) in [
+            (1, "Honey never spoils"),
+            (2, "Octopuses have three hearts"),
+        ] {
+            let fact = fact(id, title);
+            kinds.get("facts").unwrap().create(fact).await.unwrap();
+        }
+        let principle =
+            Entry::new(Kind::new("principles").unwrap(), 1, "Honey, honey, honey").unwrap();
+        kinds
+            .get("principles")
+            .unwrap()
+            .create(principle)
+            .await
+            .unwrap();
+        let app = test::init_service(App::new().configure(with_store(store, kinds))).await;
+        let find = |query: &str| send(&app, test::TestRequest::get().uri(query).to_request());
+
+        let (status, hits) = find("/search?q=HONEY").await;
+        assert_eq!(status, StatusCode::OK);
+        let found: Vec<_> = (hits.as_array().unwrap().iter())
+            .map(|hit| {
+                (
+                    hit["kind"].as_str().unwrap(),
+                    hit["item"]["id"].as_i64().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(found.len(), 2);
+        assert!(found.contains(&("facts", 1)) && found.contains(&("principles", 1)));
+        assert!(hits[0]["score"].as_f64() >= hits[1]["score"].as_f64());
+        let fact = (hits.as_array().unwrap().iter())
+            .find(|hit| hit["kind"] == "facts")
+            .unwrap();
+        assert_eq!(fact["matches"], json!([{"start": 0, "end": 5}]));
+
+        let (_, hits) = find("/search?q=honey&kind=facts&limit=1").await;
+        assert_eq!(hits.as_array().unwrap().len(), 1);
+        assert_eq!(hits[0]["kind"], "facts");
+        assert_eq!(
+            find("/search?q=hearts&kind=rules").await.0,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    /// Cheap enough for tests. Real deployments keep the defaults or raise them.
+    fn auth_settings(session_ttl: chrono::Duration) -> Data<AuthSettings> {
+        Data::new(AuthSettings {
+            memory_kib: 8,
+            iterations: 1,
+            parallelism: 1,
+            session_ttl,
+        })
+    }
+
+    #[actix_web::test]
+    async fn registers_logs_in_and_out() {
+        let store: Arc<dyn DioStore> = Arc::new(MemoryStore::default());
+        let kinds = kinds_of(&store).await;
+        let app = App::new()
+            .configure(with_store(store, kinds))
+            .app_data(auth_settings(chrono::Duration::hours(1)));
+        let app = test::init_service(app).await;
+        let post = |uri: &str, body: Value| {
+            let req = test::TestRequest::post().uri(uri).set_json(body);
+            send(&app, req.to_request())
+        };
+        let ada = json!({"username": "ada", "password": "analytical"});
+
+        let (status, user) = post("/users", ada.clone()).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(user["username"], "ada");
+        assert!(user.get("password_hash").is_none());
+        assert_eq!(post("/users", ada.clone()).await.0, StatusCode::CONFLICT);
+        let bad_name = json!({"username": "a d", "password": "analytical"});
+        assert_eq!(post("/users", bad_name).await.0, StatusCode::BAD_REQUEST);
+        let short = json!({"username": "bob", "password": "short"});
+        assert_eq!(post("/users", short).await.0, StatusCode::BAD_REQUEST);
+
+        let wrong = json!({"username": "ada", "password": "difference"});
+        assert_eq!(post("/login", wrong).await.0, StatusCode::UNAUTHORIZED);
+        let unknown = json!({"username": "bob", "password": "analytical"});
+        assert_eq!(post("/login", unknown).await.0, StatusCode::UNAUTHORIZED);
+        let (status, session) = post("/login", ada).await;
+        assert_eq!(status, StatusCode::OK);
+        let bearer = (
+            header::AUTHORIZATION,
+            format!("Bearer {}", session["token"].as_str().unwrap()),
+        );
+
+        let whoami = || test::TestRequest::get().uri("/me");
+        let (status, user) = send(&app, whoami().insert_header(bearer.clone()).to_request()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            user,
+            json!({"kind": "user", "username": "ada", "role": "reader"})
+        );
+        assert_eq!(
+            send(&app, whoami().to_request()).await.0,
+            StatusCode::UNAUTHORIZED
+        );
+
+        let req = test::TestRequest::post().uri("/logout");
+        let req = req.insert_header(bearer.clone()).to_request();
+        assert_eq!(send(&app, req).await.0, StatusCode::NO_CONTENT);
+        let req = whoami().insert_header(bearer).to_request();
+        assert_eq!(send(&app, req).await.0, StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn expires_sessions() {
+        let store: Arc<dyn DioStore> = Arc::new(MemoryStore::default());
+        let kinds = kinds_of(&store).await;
+        let app = App::new()
+            .configure(with_store(store.clone(), kinds))
+            .app_data(auth_settings(chrono::Duration::zero()));
+        let app = test::init_service(app).await;
+        let ada = json!({"username": "ada", "password": "analytical"});
+        let req = test::TestRequest::post().uri("/users").set_json(&ada);
+        assert_eq!(send(&app, req.to_request()).await.0, StatusCode::CREATED);
+        let req = test::TestRequest::post().uri("/login").set_json(&ada);
+        let (_, session) = send(&app, req.to_request()).await;
+        let token = session["token"].as_str().unwrap();
+
+        let req = test::TestRequest::get()
+            .uri("/me")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {token}")));
+        assert_eq!(
+            send(&app, req.to_request()).await.0,
+            StatusCode::UNAUTHORIZED
+        );
+        let session = store.sessions().get(&auth::token_digest(token)).await;
+        assert!(session.unwrap().is_none());
+    }
+
+    #[actix_web::test]
+    async fn scopes_api_keys() {
+        let store: Arc<dyn DioStore> = Arc::new(MemoryStore::default());
+        let kinds = kinds_of(&store).await;
+        let (admin, user) = (
+            sign_in(&*store, "ada", Role::Admin).await,
+            sign_in(&*store, "bob", Role::Editor).await,
+        );
+        let app = App::new()
+            .configure(with_store(store, kinds))
+            .app_data(auth_settings(chrono::Duration::hours(1)));
+        let app = test::init_service(app).await;
+        let mint = |signed_in: &(header::HeaderName, String), scope: &str| {
+            let req = test::TestRequest::post()
+                .uri("/admin/api-keys")
+                .insert_header(signed_in.clone())
+                .set_json(json!({"name": "importer", "scope": scope}));
+            send(&app, req.to_request())
+        };
+        let create = |key: &Value| {
+            let bearer = format!("Bearer {}", key["token"].as_str().unwrap());
+            let req = test::TestRequest::post()
+                .uri("/facts")
+                .insert_header((header::AUTHORIZATION, bearer))
+                .set_json(json!({"title": "Cats purr"}));
+            send(&app, req.to_request())
+        };
+
+        assert_eq!(mint(&user, "write").await.0, StatusCode::FORBIDDEN);
+        let (status, reader) = mint(&admin, "read").await;
+        assert_eq!(status, StatusCode::CREATED);
+        let (_, writer) = mint(&admin, "write").await;
+        assert!(writer["token"].as_str().unwrap().starts_with("dio_"));
+        assert_eq!(create(&reader).await.0, StatusCode::FORBIDDEN);
+        assert_eq!(create(&writer).await.0, StatusCode::CREATED);
+
+        let req = test::TestRequest::get()
+            .uri("/admin/api-keys")
+            .insert_header(admin.clone());
+        let (_, keys) = send(&app, req.to_request()).await;
+        assert_eq!(keys.as_array().unwrap().len(), 2);
+        assert!(keys[0].get("token").is_none() && keys[0].get("token_digest").is_none());
+        let req = test::TestRequest::delete()
+            .uri(&format!(
+                "/admin/api-keys/{}",
+                writer["id"].as_str().unwrap()
+            ))
+            .insert_header(admin);
+        assert_eq!(send(&app, req.to_request()).await.0, StatusCode::NO_CONTENT);
+        assert_eq!(create(&writer).await.0, StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn requires_roles_per_route() {
+        let store: Arc<dyn DioStore> = Arc::new(MemoryStore::default());
+        let kinds = kinds_of(&store).await;
+        let reader = sign_in(&*store, "rey", Role::Reader).await;
+        let editor = sign_in(&*store, "eddie", Role::Editor).await;
+        let admin = sign_in(&*store, "ada", Role::Admin).await;
+        let app = test::init_service(App::new().configure(with_store(store, kinds))).await;
+        let create = || {
+            test::TestRequest::post()
+                .uri("/facts")
+                .set_json(json!({"id": 1, "title": "Cats purr"}))
+        };
+
+        let (status, body) = send(&app, create().to_request()).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["code"], "unauthorized");
+        let (status, body) = send(&app, create().insert_header(reader).to_request()).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(
+            (&body["required_role"], &body["role"]),
+            (&json!("editor"), &json!("reader"))
+        );
+        let req = create().insert_header(editor.clone()).to_request();
+        assert_eq!(send(&app, req).await.0, StatusCode::CREATED);
+
+        let delete = || test::TestRequest::delete().uri("/facts/1");
+        let (status, body) = send(&app, delete().insert_header(editor).to_request()).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["required_role"], "admin");
+        let req = delete().insert_header(admin.clone()).to_request();
+        assert_eq!(send(&app, req).await.0, StatusCode::NO_CONTENT);
+
+        let req = test::TestRequest::put()
+            .uri("/admin/users/rey/role")
+            .insert_header(admin)
+            .set_json(json!({"role": "editor"}));
+        let (status, user) = send(&app, req.to_request()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(user["role"], "editor");
+    }
+
+    #[actix_web::test]
+    async fn limits_rates_per_client() {
+        let store: Arc<dyn DioStore> = Arc::new(MemoryStore::default());
+        let kinds = kinds_of(&store).await;
+        let ada = sign_in(&*store, "ada", Role::Reader).await;
+        let budget = Budget {
+            burst: 2,
+            per_second: 0.5,
+        };
+        let app = test::init_service(App::new().configure(with_budget(store, kinds, budget))).await;
+        let list = |peer: &str, signed_in: Option<&(header::HeaderName, String)>| {
+            let mut req = test::TestRequest::get()
+                .uri("/facts")
+                .peer_addr(format!("{peer}:4000").parse().unwrap());
+            if let Some(signed_in) = signed_in {
+                req = req.insert_header(signed_in.clone());
+            }
+            test::call_service(&app, req.to_request())
+        };
+        let header = |res: &ServiceResponse<_>, name: &str| {
+            res.headers()
+                .get(name)
+                .map(|value| value.to_str().unwrap().to_owned())
+        };
+
+        let res = list("10.0.0.1", None).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(header(&res, "ratelimit-limit").as_deref(), Some("2"));
+        assert_eq!(header(&res, "ratelimit-remaining").as_deref(), Some("1"));
+        assert_eq!(header(&res, "ratelimit-reset").as_deref(), Some("2"));
+        assert_eq!(list("10.0.0.1", None).await.status(), StatusCode::OK);
+        let res = list("10.0.0.1", None).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(header(&res, "retry-after").as_deref(), Some("2"));
+        assert_eq!(header(&res, "ratelimit-remaining").as_deref(), Some("0"));
+
+        // Signed-in requests are charged to the user, and their peer refunded.
+        assert_eq!(list("10.0.0.2", Some(&ada)).await.status(), StatusCode::OK);
+        let res = list("10.0.0.2", Some(&ada)).await;
+        assert_eq!(header(&res, "ratelimit-remaining").as_deref(), Some("0"));
+        let res = list("10.0.0.2", Some(&ada)).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        let res = list("10.0.0.2", None).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(header(&res, "ratelimit-remaining").as_deref(), Some("1"));
+        // An exhausted peer is turned away before its token is looked up.
+        let res = list("10.0.0.1", Some(&ada)).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[actix_web::test]
+    async fn answers_errors_as_problem_json() {
+        let store: Arc<dyn DioStore> = Arc::new(MemoryStore::default());
+        let kinds = kinds_of(&store).await;
+        let ada = sign_in(&*store, "ada", Role::Editor).await;
+        let budget = Budget {
+            burst: 3,
+            per_second: 0.5,
+        };
+        let app = test::init_service(App::new().configure(with_budget(store, kinds, budget))).await;
+        let create = |fact: Value| {
+            test::TestRequest::post()
+                .uri("/facts")
+                .insert_header(ada.clone())
+                .set_json(fact)
+                .to_request()
+        };
+        assert_eq!(
+            send(&app, create(json!({"id": 1, "title": "Cats purr"})))
+                .await
+                .0,
+            StatusCode::CREATED
+        );
+
+        let cases = [
+            (
+                test::TestRequest::get().uri("/facts/7").to_request(),
+                StatusCode::NOT_FOUND,
+                "not_found",
+            ),
+            (
+                create(json!({"id": 1, "title": "Cats purr"})),
+                StatusCode::CONFLICT,
+                "conflict",
+            ),
+            (
+                create(json!({"title": 7})),
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "invalid_record",
+            ),
+            (
+                create(json!({"title": "Dogs bark"})),
+                StatusCode::TOO_MANY_REQUESTS,
+                "rate_limited",
+            ),
+        ];
+        for (req, status, code) in cases {
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), status);
+            let content_type = res.headers().get(header::CONTENT_TYPE).unwrap();
+            assert_eq!(content_type, "application/problem+json");
+            let problem: Value = test::read_body_json(res).await;
+            assert_eq!(problem["status"], status.as_u16());
+            assert_eq!(problem["type"], format!("urn:dio:problem:{code}"));
+            assert_eq!(problem["title"], status.canonical_reason().unwrap());
+            assert_eq!(problem["code"], code);
+        }
+    }
+
+    #[actix_web::test]
+    async fn answers_ready_once_the_database_answered() {
+        let store: Arc<dyn DioStore> = Arc::new(MemoryStore::default());
+        let kinds = kinds_of(&store).await;
+        let readiness = Data::new(Readiness::default());
+        let app = test::init_service(
+            App::new()
+                .app_data(readiness.clone())
+                .configure(with_store(store, kinds)),
+        )
+        .await;
+        let probe = |uri| test::TestRequest::get().uri(uri).to_request();
+
+        let (status, body) = send(&app, probe("/health/live")).await;
+        assert_eq!((status, &body["status"]), (StatusCode::OK, &json!("ok")));
+        let (status, body) = send(&app, probe("/health/ready")).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["status"], "unavailable");
+        assert_eq!(body["checks"]["database"]["status"], "starting");
+
+        readiness.set_ready(true);
+        for uri in ["/health/ready", "/healthcheck"] {
+            let (status, body) = send(&app, probe(uri)).await;
+            assert_eq!((status, &body["status"]), (StatusCode::OK, &json!("ok")));
+            assert_eq!(body["checks"]["database"]["status"], "ok");
+            assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+        }
+
+        // Shutting down takes the instance out of rotation while it drains.
+        readiness.set_ready(false);
+        let (status, _) = send(&app, probe("/health/ready")).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        let (status, _) = send(&app, probe("/health/live")).await;
+        assert_eq!(status, StatusCode::OK);
+    }
 }
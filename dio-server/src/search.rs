@@ -0,0 +1,151 @@
+//! `search` is the full-text search used by stores without a native text index.
+//!
+//! Titles are split into lowercase alphanumeric tokens and ranked with BM25.
+//! The same tokenizer finds the spans highlighted in search results, whichever
+//! store ranked them.
+
+use serde::Serialize;
+use std::collections::HashMap;
+use utoipa::ToSchema;
+
+/// BM25 term frequency saturation.
+const K1: f64 = 1.2;
+/// BM25 document length normalization.
+const B: f64 = 0.75;
+
+/// A run of `char`s in a title, `start` inclusive and `end` exclusive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, ToSchema)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Splits `text` into lowercase alphanumeric words and their spans.
+pub fn tokenize(text: &str) -> Vec<(String, Span)> {
+    let mut tokens = Vec::new();
+    let mut word = String::new();
+    let mut start = 0;
+    for (i, c) in text.chars().enumerate() {
+        if c.is_alphanumeric() {
+            if word.is_empty() {
+                start = i;
+            }
+            word.extend(c.to_lowercase());
+        } else if !word.is_empty() {
+            tokens.push((std::mem::take(&mut word), Span { start, end: i }));
+        }
+    }
+    if !word.is_empty() {
+        let end = text.chars().count();
+        tokens.push((word, Span { start, end }));
+    }
+    tokens
+}
+
+/// Spans of the words in `text` that appear in `query`.
+pub fn highlights(text: &str, query: &str) -> Vec<Span> {
+    let terms: Vec<String> = tokenize(query).into_iter().map(|(term, _)| term).collect();
+    tokenize(text)
+        .into_iter()
+        .filter(|(word, _)| terms.contains(word))
+        .map(|(_, span)| span)
+        .collect()
+}
+
+/// Inverted index from words to the ids of the records whose title contains them.
+#[derive(Debug, Default)]
+pub struct Index {
+    /// Word to record id to occurrences.
+    postings: HashMap<String, HashMap<i32, u32>>,
+    /// Record id to number of words in its title.
+    lengths: HashMap<i32, u32>,
+}
+
+impl Index {
+    /// Indexes `text` under `id`, replacing what was indexed for `id` before.
+    pub fn insert(&mut self, id: i32, text: &str) {
+        self.remove(id);
+        let tokens = tokenize(text);
+        self.lengths.insert(id, tokens.len() as u32);
+        for (word, _) in tokens {
+            *self
+                .postings
+                .entry(word)
+                .or_default()
+                .entry(id)
+                .or_default() += 1;
+        }
+    }
+
+    pub fn remove(&mut self, id: i32) {
+        if self.lengths.remove(&id).is_none() {
+            return;
+        }
+        self.postings.retain(|_, ids| {
+            ids.remove(&id);
+            !ids.is_empty()
+        });
+    }
+
+    /// Ids matching any word of `query`, best first, with their BM25 scores.
+    pub fn search(&self, query: &str, limit: usize) -> Vec<(i32, f64)> {
+        let docs = self.lengths.len() as f64;
+        if docs == 0.0 {
+            return Vec::new();
+        }
+        let avg_len = self.lengths.values().sum::<u32>() as f64 / docs;
+
+        let mut terms: Vec<String> = tokenize(query).into_iter().map(|(term, _)| term).collect();
+        terms.sort();
+        terms.dedup();
+
+        let mut scores: HashMap<i32, f64> = HashMap::new();
+        for term in &terms {
+            let Some(ids) = self.postings.get(term) else {
+                continue;
+            };
+            let matching = ids.len() as f64;
+            let idf = ((docs - matching + 0.5) / (matching + 0.5) + 1.0).ln();
+            for (id, &freq) in ids {
+                let freq = freq as f64;
+                let len = self.lengths[id] as f64;
+                let norm = freq * (K1 + 1.0) / (freq + K1 * (1.0 - B + B * len / avg_len));
+                *scores.entry(*id).or_default() += idf * norm;
+            }
+        }
+
+        let mut ranked: Vec<(i32, f64)> = scores.into_iter().collect();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        ranked.truncate(limit);
+        ranked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranks_rarer_and_denser_matches_first() {
+        let mut index = Index::default();
+        index.insert(1, "Honey never spoils");
+        index.insert(2, "Bees make honey, honey, honey");
+        index.insert(3, "Octopuses have three hearts");
+        let ids = |hits: Vec<(i32, f64)>| hits.into_iter().map(|(id, _)| id).collect::<Vec<_>>();
+
+        assert_eq!(ids(index.search("honey", 10)), [2, 1]);
+        assert_eq!(ids(index.search("HEARTS of honey", 1)), [3]);
+        index.insert(2, "Bees dance");
+        index.remove(3);
+        assert_eq!(ids(index.search("honey hearts", 10)), [1]);
+    }
+
+    #[test]
+    fn highlights_words_of_the_query() {
+        let spans = highlights("Élan, élan vital!", "ÉLAN");
+        assert_eq!(
+            spans,
+            [Span { start: 0, end: 4 }, Span { start: 6, end: 10 }]
+        );
+    }
+}
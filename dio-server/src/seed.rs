@@ -0,0 +1,190 @@
+//! `seed` loads the `data.json` file of dio-cli into the store, for
+//! `dio-server seed <file>`.
+//!
+//! The file is read as a [`DataFile`], so entries are normalized and
+//! validated the same way as in dio-cli. Seeding again after editing the file
+//! only writes the entries whose text or tags changed or that are new, so it
+//! can be run on every deploy. Kinds of the file the server does not know yet
+//! are registered, as `POST /kinds` would.
+
+use crate::{
+    config::Config,
+    db::{self, Kinds, Repo},
+    model::{Entry, KindDef},
+};
+use anyhow::Context;
+use chrono::{Timelike, Utc};
+use dio_core::DataFile;
+use std::{fmt, fs::File, io::BufReader, path::Path};
+
+/// What seeding one collection did.
+#[derive(Debug, Default)]
+struct Seeded {
+    created: usize,
+    updated: usize,
+    unchanged: usize,
+    /// Records with ids past the end of the file.
+    extra: usize,
+    pruned: bool,
+}
+
+impl fmt::Display for Seeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} created, {} updated, {} unchanged",
+            self.created, self.updated, self.unchanged
+        )?;
+        match (self.extra, self.pruned) {
+            (0, _) => Ok(()),
+            (extra, true) => write!(f, ", {extra} not in the file deleted"),
+            (extra, false) => write!(f, ", {extra} not in the file kept (see --prune)"),
+        }
+    }
+}
+
+/// Seeds the store of `config` from the file at `path`. With `prune`, records
+/// whose id is past the end of their list are deleted.
+pub async fn run(config: &Config, path: &Path, prune: bool) -> anyhow::Result<()> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let data: DataFile = serde_json::from_reader(BufReader::new(file))
+        .with_context(|| format!("Failed to read {} as a dio-cli data file", path.display()))?;
+    let entries = (data.kinds())
+        .map(|kind| Ok((kind.clone(), data.entries(kind)?)))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let store = db::init_store(config).await?;
+    db::connect(store.as_ref(), config).await?;
+    let seeded = async {
+        let kinds = Kinds::open(store.clone(), config).await?;
+        kinds.prepare().await?;
+        for (kind, entries) in entries {
+            let repo = match kinds.get(kind.as_str()) {
+                Some(repo) => repo,
+                None => {
+                    let def = KindDef {
+                        kind: kind.clone(),
+                        created_by: "seed".to_owned(),
+                        created_at: Utc::now().with_nanosecond(0).unwrap(),
+                    };
+                    let repo = kinds.register(def).await?;
+                    println!("{kind}: registered");
+                    repo
+                }
+            };
+            let seeded = seed(repo.as_ref(), entries, prune).await?;
+            println!("{kind}: {seeded}");
+        }
+        anyhow::Ok(())
+    }
+    .await;
+    store.close().await;
+    seeded
+}
+
+async fn seed(repo: &dyn Repo<Entry>, entries: Vec<Entry>, prune: bool) -> anyhow::Result<Seeded> {
+    let mut seeded = Seeded::default();
+    let mut changed = Vec::new();
+    let count = entries.len();
+    let now = Utc::now().with_nanosecond(0).unwrap();
+    for mut entry in entries {
+        match repo.get(entry.id).await? {
+            Some(stored) if stored.text == entry.text && stored.tags == entry.tags => {
+                seeded.unchanged += 1
+            }
+            Some(mut stored) => {
+                stored.text = entry.text;
+                stored.tags = entry.tags;
+                stored.updated_at = Some(now);
+                changed.push(stored);
+                seeded.updated += 1;
+            }
+            None => {
+                entry.created_at = Some(now);
+                changed.push(entry);
+                seeded.created += 1;
+            }
+        }
+    }
+    // All or nothing, so a failed run leaves the previous seed in place.
+    repo.import(changed, true).await?;
+
+    let extra = (repo.ids().await?.into_iter())
+        .filter(|&id| usize::try_from(id).is_ok_and(|id| id > count))
+        .collect::<Vec<_>>();
+    seeded.extra = extra.len();
+    if prune {
+        for id in extra {
+            repo.delete(id).await?;
+        }
+        seeded.pruned = true;
+    }
+    Ok(seeded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::{DioStore, MemoryStore},
+        model::Kind,
+    };
+    use std::sync::Arc;
+
+    fn entries(texts: &[&str]) -> Vec<Entry> {
+        let kind = Kind::new("facts").unwrap();
+        (1..)
+            .zip(texts)
+            .map(|(id, text)| Entry::new(kind.clone(), id, text).unwrap())
+            .collect()
+    }
+
+    async fn facts() -> Arc<dyn Repo<Entry>> {
+        let kind = Kind::new("facts").unwrap();
+        MemoryStore::default()
+            .entries(&kind, "facts")
+            .await
+            .unwrap()
+    }
+
+    #[actix_web::test]
+    async fn seeds_again_without_duplicates() {
+        let repo = facts().await;
+        let facts = repo.as_ref();
+        let file = entries(&["Cats purr.", "Dogs bark.", "Owls hoot."]);
+        let seeded = seed(facts, file.clone(), false).await.unwrap();
+        assert_eq!(seeded.to_string(), "3 created, 0 updated, 0 unchanged");
+        let created_at = facts.get(2).await.unwrap().unwrap().created_at;
+
+        let seeded = seed(facts, file, false).await.unwrap();
+        assert_eq!(seeded.to_string(), "0 created, 0 updated, 3 unchanged");
+        assert_eq!(facts.ids().await.unwrap(), [1, 2, 3]);
+
+        let file = entries(&["Cats purr.", "Dogs woof."]);
+        let seeded = seed(facts, file, false).await.unwrap();
+        assert_eq!(
+            seeded.to_string(),
+            "0 created, 1 updated, 1 unchanged, 1 not in the file kept (see --prune)"
+        );
+        let updated = facts.get(2).await.unwrap().unwrap();
+        assert_eq!(updated.text, "Dogs woof.");
+        assert_eq!(updated.created_at, created_at);
+        assert!(updated.updated_at.is_some());
+        assert_eq!(facts.ids().await.unwrap(), [1, 2, 3]);
+    }
+
+    #[actix_web::test]
+    async fn prunes_records_past_the_end_of_the_file() {
+        let repo = facts().await;
+        let facts = repo.as_ref();
+        let file = entries(&["Cats purr.", "Dogs bark.", "Owls hoot."]);
+        seed(facts, file, false).await.unwrap();
+
+        let seeded = seed(facts, entries(&["Cats purr."]), true).await.unwrap();
+        assert_eq!(
+            seeded.to_string(),
+            "0 created, 0 updated, 1 unchanged, 2 not in the file deleted"
+        );
+        assert_eq!(facts.ids().await.unwrap(), [1]);
+    }
+}
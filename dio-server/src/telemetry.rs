@@ -0,0 +1,131 @@
+//! `telemetry` sets up structured logging and traces every request.
+//!
+//! Logs go to stdout, as JSON or pretty text depending on `log_format`. Each
+//! request runs in a span carrying its id, method and path; storage operations
+//! are child spans of it, see `db::MeteredStore`.
+
+use crate::config::{Config, LogFormat, LogLevel};
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::{HeaderName, HeaderValue},
+};
+use futures::future::{ready, LocalBoxFuture, Ready};
+use rand::RngCore;
+use std::{rc::Rc, time::Instant};
+use tracing::{level_filters::LevelFilter, Instrument};
+
+/// Header carrying the id of a request, both ways.
+pub const REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Installs the global subscriber. Call once, before anything logs.
+pub fn init(config: &Config) {
+    let level = match config.log_level {
+        LogLevel::Error => LevelFilter::ERROR,
+        LogLevel::Warn => LevelFilter::WARN,
+        LogLevel::Info => LevelFilter::INFO,
+        LogLevel::Debug => LevelFilter::DEBUG,
+        LogLevel::Trace => LevelFilter::TRACE,
+    };
+    let subscriber = tracing_subscriber::fmt().with_max_level(level);
+    match config.log_format {
+        LogFormat::Pretty => subscriber.pretty().init(),
+        LogFormat::Json => subscriber.json().with_current_span(false).init(),
+    }
+}
+
+/// The id sent by the client, if it looks like one, else a new random one.
+fn request_id(req: &ServiceRequest) -> String {
+    let sent = req
+        .headers()
+        .get(&REQUEST_ID)
+        .and_then(|id| id.to_str().ok());
+    match sent {
+        Some(id)
+            if (1..=128).contains(&id.len())
+                && (id.chars()).all(|c| c.is_ascii_alphanumeric() || "-_.:".contains(c)) =>
+        {
+            id.to_owned()
+        }
+        _ => {
+            let mut id = [0u8; 8];
+            rand::thread_rng().fill_bytes(&mut id);
+            hex::encode(id)
+        }
+    }
+}
+
+/// Middleware that runs every request in a span, logs its status and latency
+/// when it is answered, and echoes its id in `X-Request-Id`.
+pub struct Trace;
+
+impl<S, B> Transform<S, ServiceRequest> for Trace
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = TraceMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(TraceMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct TraceMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for TraceMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let id = request_id(&req);
+        let span = tracing::info_span!(
+            "request",
+            request_id = %id,
+            method = %req.method(),
+            path = %req.path(),
+        );
+        Box::pin(
+            async move {
+                let started = Instant::now();
+                let res = service.call(req).await;
+                let latency_ms = (started.elapsed().as_secs_f64() * 1e6).round() / 1e3;
+                let mut res = match res {
+                    Ok(res) => res,
+                    Err(err) => {
+                        let status = err.as_response_error().status_code().as_u16();
+                        tracing::error!(status, latency_ms, error = %err, "request failed");
+                        return Err(err);
+                    }
+                };
+
+                let status = res.status();
+                if status.is_server_error() {
+                    tracing::error!(status = status.as_u16(), latency_ms, "request answered");
+                } else {
+                    tracing::info!(status = status.as_u16(), latency_ms, "request answered");
+                }
+                if let Ok(id) = HeaderValue::from_str(&id) {
+                    res.headers_mut().insert(REQUEST_ID, id);
+                }
+                Ok(res)
+            }
+            .instrument(span),
+        )
+    }
+}
@@ -1,11 +1,4 @@
 //! `util` contains common utility functions agnostice to the project.
-use std::env;
-
-#[inline(always)]
-pub fn get_env_var(key: &str) -> Result<String, Box<dyn std::error::Error>> {
-    Ok(env::var(key)
-        .unwrap_or_else(|_| panic!("{}", format!("{} environment variable not set.", key))))
-}
 
 // mod generics {
 //     fn main_run() {